@@ -0,0 +1,1320 @@
+//! Arbitrary precision integers in plain Rust, the crate's `Integer`
+//! without the `gmp` feature (see `arith::integer`).
+//!
+//! Only the part of rug's interface the crate uses is provided, with the
+//! same names and semantics, so that the code reads the same with either
+//! type. Where rug returns an incomplete computation value, the result is
+//! computed right away, which `Integer::from` then takes as is.
+//!
+//! Magnitudes are vectors of 64-bit limbs, least significant first.
+//! Multiplication switches from schoolbook to Karatsuba for large
+//! operands, division is Knuth's algorithm D.
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{
+    Add, AddAssign, BitOr, Div, DivAssign, Mul, MulAssign, Neg, Rem, RemAssign, Shl, ShlAssign,
+    Shr, ShrAssign, Sub, SubAssign,
+};
+use std::str::FromStr;
+
+/// Signed integer, the sign apart from the magnitude
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Integer {
+    /// Never set for zero
+    neg: bool,
+    /// Least significant limb first, without high zero limbs
+    mag: Vec<u64>,
+}
+
+/// Outcome of `Integer::is_probably_prime`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IsPrime {
+    No,
+    Probably,
+    Yes,
+}
+
+/// Order of the digits of `to_digits`, `from_digits` and `write_digits`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Order {
+    /// Least significant digit first
+    Lsf,
+    /// Most significant digit first
+    Msf,
+}
+
+/// Digit types of `to_digits` and friends, only u64 limbs here
+pub trait Digit: Copy {
+    fn from_limb(limb: u64) -> Self;
+    fn to_limb(self) -> u64;
+}
+
+impl Digit for u64 {
+    fn from_limb(limb: u64) -> u64 {
+        limb
+    }
+
+    fn to_limb(self) -> u64 {
+        self
+    }
+}
+
+/// Exponentiation, as rug's `ops::Pow`
+pub trait Pow<Rhs> {
+    type Output;
+    fn pow(self, rhs: Rhs) -> Self::Output;
+}
+
+/// Assignment, as rug's `Assign`
+pub trait Assign<Src = Self> {
+    fn assign(&mut self, src: Src);
+}
+
+/// Error of `Integer::from_str_radix` and `Integer::from_str`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseIntegerError;
+
+impl fmt::Display for ParseIntegerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid integer")
+    }
+}
+
+impl std::error::Error for ParseIntegerError {}
+
+/// Random number generator of `Integer::random_bits` and
+/// `random_below_ref`, SplitMix64. Not cryptographic: it draws sigmas and
+/// test inputs.
+#[derive(Clone, Debug)]
+pub struct RandState {
+    state: u64,
+}
+
+impl Default for RandState {
+    fn default() -> RandState {
+        RandState::new()
+    }
+}
+
+impl RandState {
+    /// A generator with a fixed seed, as rug's
+    pub fn new() -> RandState {
+        RandState { state: 0 }
+    }
+
+    pub fn seed(&mut self, seed: &Integer) {
+        self.state = seed.mag.iter().fold(0, |s: u64, &l| {
+            s.rotate_left(17) ^ l.wrapping_mul(0x9e3779b97f4a7c15)
+        });
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A random number of bits bits, bits <= 32
+    pub fn bits(&mut self, bits: u32) -> u32 {
+        assert!(bits <= 32, "at most 32 bits");
+        (self.next_u64() >> (64 - bits.max(1))) as u32 & (((1u64 << bits) - 1) as u32)
+    }
+
+    /// A random number below bound, bound > 0
+    pub fn below(&mut self, bound: u32) -> u32 {
+        assert!(bound > 0, "empty range");
+        (((self.next_u64() >> 32) * bound as u64) >> 32) as u32
+    }
+}
+
+// Operations on magnitudes. They accept high zero limbs and return
+// trimmed magnitudes.
+
+fn trim(mut v: Vec<u64>) -> Vec<u64> {
+    while v.last() == Some(&0) {
+        v.pop();
+    }
+    v
+}
+
+fn trimmed(a: &[u64]) -> &[u64] {
+    let len = a.iter().rposition(|&l| l != 0).map_or(0, |i| i + 1);
+    &a[..len]
+}
+
+fn cmp_mag(a: &[u64], b: &[u64]) -> Ordering {
+    let (a, b) = (trimmed(a), trimmed(b));
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+/// r += a << (64 shift), r being long enough
+fn add_at(r: &mut [u64], a: &[u64], shift: usize) {
+    let mut carry = false;
+    for (i, &x) in a.iter().enumerate() {
+        let (s, c1) = r[shift + i].overflowing_add(x);
+        let (s, c2) = s.overflowing_add(carry as u64);
+        r[shift + i] = s;
+        carry = c1 || c2;
+    }
+    let mut i = shift + a.len();
+    while carry {
+        let (s, c) = r[i].overflowing_add(1);
+        r[i] = s;
+        carry = c;
+        i += 1;
+    }
+}
+
+/// r -= a, with r >= a
+fn sub_assign_mag(r: &mut [u64], a: &[u64]) {
+    let mut borrow = false;
+    for (i, &x) in a.iter().enumerate() {
+        let (s, b1) = r[i].overflowing_sub(x);
+        let (s, b2) = s.overflowing_sub(borrow as u64);
+        r[i] = s;
+        borrow = b1 || b2;
+    }
+    let mut i = a.len();
+    while borrow {
+        let (s, b) = r[i].overflowing_sub(1);
+        r[i] = s;
+        borrow = b;
+        i += 1;
+    }
+}
+
+fn add_mag(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut r = Vec::with_capacity(a.len() + 1);
+    r.extend_from_slice(a);
+    r.push(0);
+    add_at(&mut r, b, 0);
+    trim(r)
+}
+
+/// a - b, with a >= b
+fn sub_mag(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut r = a.to_vec();
+    sub_assign_mag(&mut r, trimmed(b));
+    trim(r)
+}
+
+/// Below this many limbs in the shorter operand, schoolbook
+const KARATSUBA_LIMBS: usize = 32;
+
+fn mul_mag(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let (a, b) = (trimmed(a), trimmed(b));
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    if b.is_empty() {
+        return vec![];
+    }
+    if b.len() < KARATSUBA_LIMBS {
+        return schoolbook(a, b);
+    }
+    if 2 * b.len() <= a.len() {
+        // b times each chunk of a of b's length
+        let mut r = vec![0; a.len() + b.len() + 1];
+        for (i, chunk) in a.chunks(b.len()).enumerate() {
+            add_at(&mut r, &mul_mag(chunk, b), i * b.len());
+        }
+        return trim(r);
+    }
+    // a = a1 B^h + a0, b = b1 B^h + b0 with b1 nonzero as b > a / 2
+    let h = a.len() / 2;
+    let (a0, a1, b0, b1) = (&a[..h], &a[h..], &b[..h], &b[h..]);
+    let z0 = mul_mag(a0, b0);
+    let z2 = mul_mag(a1, b1);
+    let mut z1 = mul_mag(&add_mag(a0, a1), &add_mag(b0, b1));
+    sub_assign_mag(&mut z1, &z0);
+    sub_assign_mag(&mut z1, &z2);
+    let mut r = vec![0; a.len() + b.len() + 1];
+    add_at(&mut r, &z0, 0);
+    add_at(&mut r, trimmed(&z1), h);
+    add_at(&mut r, &z2, 2 * h);
+    trim(r)
+}
+
+fn schoolbook(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut r = vec![0; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let t = x as u128 * y as u128 + r[i + j] as u128 + carry as u128;
+            r[i + j] = t as u64;
+            carry = (t >> 64) as u64;
+        }
+        r[i + b.len()] = carry;
+    }
+    trim(r)
+}
+
+/// a / d and a % d for a single limb d > 0
+fn divrem_limb(a: &[u64], d: u64) -> (Vec<u64>, u64) {
+    let mut q = vec![0; a.len()];
+    let mut r = 0u64;
+    for i in (0..a.len()).rev() {
+        let t = (r as u128) << 64 | a[i] as u128;
+        q[i] = (t / d as u128) as u64;
+        r = (t % d as u128) as u64;
+    }
+    (trim(q), r)
+}
+
+fn shl_mag(a: &[u64], bits: usize) -> Vec<u64> {
+    let a = trimmed(a);
+    if a.is_empty() {
+        return vec![];
+    }
+    let (limbs, bits) = (bits / 64, bits % 64);
+    let mut r = vec![0; limbs];
+    if bits == 0 {
+        r.extend_from_slice(a);
+    } else {
+        let mut carry = 0;
+        for &x in a {
+            r.push(x << bits | carry);
+            carry = x >> (64 - bits);
+        }
+        r.push(carry);
+    }
+    trim(r)
+}
+
+fn shr_mag(a: &[u64], bits: usize) -> Vec<u64> {
+    let (limbs, bits) = (bits / 64, bits % 64);
+    if limbs >= a.len() {
+        return vec![];
+    }
+    let a = &a[limbs..];
+    if bits == 0 {
+        return trim(a.to_vec());
+    }
+    let r = (0..a.len())
+        .map(|i| a[i] >> bits | a.get(i + 1).map_or(0, |&x| x << (64 - bits)))
+        .collect();
+    trim(r)
+}
+
+/// a / b and a % b, Knuth's algorithm D, b nonzero
+fn divrem_mag(a: &[u64], b: &[u64]) -> (Vec<u64>, Vec<u64>) {
+    let (a, b) = (trimmed(a), trimmed(b));
+    assert!(!b.is_empty(), "division by zero");
+    if cmp_mag(a, b) == Ordering::Less {
+        return (vec![], a.to_vec());
+    }
+    if b.len() == 1 {
+        let (q, r) = divrem_limb(a, b[0]);
+        return (q, trim(vec![r]));
+    }
+    // normalized so that the top limb of v has its high bit set
+    let s = b[b.len() - 1].leading_zeros() as usize;
+    let v = shl_mag(b, s);
+    let mut u = shl_mag(a, s);
+    u.resize(a.len() + 1, 0);
+    let n = v.len();
+    let m = a.len() - n;
+    let mut q = vec![0; m + 1];
+    const B: u128 = 1 << 64;
+    for j in (0..=m).rev() {
+        let num = (u[j + n] as u128) << 64 | u[j + n - 1] as u128;
+        let mut qhat = num / v[n - 1] as u128;
+        let mut rhat = num % v[n - 1] as u128;
+        while qhat >= B || qhat * v[n - 2] as u128 > (rhat << 64 | u[j + n - 2] as u128) {
+            qhat -= 1;
+            rhat += v[n - 1] as u128;
+            if rhat >= B {
+                break;
+            }
+        }
+        // u[j..j + n + 1] -= qhat v
+        let (mut borrow, mut carry) = (0u64, 0u64);
+        for i in 0..n {
+            let p = qhat * v[i] as u128 + carry as u128;
+            carry = (p >> 64) as u64;
+            let (t, b1) = u[i + j].overflowing_sub(p as u64);
+            let (t, b2) = t.overflowing_sub(borrow);
+            u[i + j] = t;
+            borrow = b1 as u64 + b2 as u64;
+        }
+        let (t, b1) = u[j + n].overflowing_sub(carry);
+        let (t, b2) = t.overflowing_sub(borrow);
+        u[j + n] = t;
+        if b1 || b2 {
+            // qhat was one too large: add v back
+            qhat -= 1;
+            let mut c = false;
+            for i in 0..n {
+                let (t, c1) = u[i + j].overflowing_add(v[i]);
+                let (t, c2) = t.overflowing_add(c as u64);
+                u[i + j] = t;
+                c = c1 || c2;
+            }
+            u[j + n] = u[j + n].wrapping_add(c as u64);
+        }
+        q[j] = qhat as u64;
+    }
+    (trim(q), shr_mag(&u[..n], s))
+}
+
+impl Integer {
+    pub const fn new() -> Integer {
+        Integer {
+            neg: false,
+            mag: Vec::new(),
+        }
+    }
+
+    fn from_parts(neg: bool, mag: Vec<u64>) -> Integer {
+        let mag = trim(mag);
+        Integer {
+            neg: neg && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    fn from_i128(v: i128) -> Integer {
+        let m = v.unsigned_abs();
+        Integer::from_parts(v < 0, vec![m as u64, (m >> 64) as u64])
+    }
+
+    fn from_u128(v: u128) -> Integer {
+        Integer::from_parts(false, vec![v as u64, (v >> 64) as u64])
+    }
+
+    /// base^exponent
+    pub fn u_pow_u(base: u32, exponent: u32) -> Integer {
+        Integer::from(base).pow(exponent)
+    }
+
+    /// A random integer of [0, 2^bits)
+    pub fn random_bits(bits: u32, rand: &mut RandState) -> Integer {
+        let limbs = (bits as usize).div_ceil(64);
+        let mut mag: Vec<u64> = (0..limbs).map(|_| rand.next_u64()).collect();
+        if !bits.is_multiple_of(64) {
+            mag[limbs - 1] >>= 64 - bits % 64;
+        }
+        Integer::from_parts(false, mag)
+    }
+
+    /// A random integer of [0, self), self > 0
+    pub fn random_below_ref(&self, rand: &mut RandState) -> Integer {
+        assert!(*self > 0, "cannot be below a nonpositive bound");
+        loop {
+            let r = Integer::random_bits(self.significant_bits(), rand);
+            if r < *self {
+                return r;
+            }
+        }
+    }
+
+    pub fn random_below(self, rand: &mut RandState) -> Integer {
+        self.random_below_ref(rand)
+    }
+
+    /// Bits of |self| up to its most significant one
+    pub fn significant_bits(&self) -> u32 {
+        match self.mag.last() {
+            Some(top) => (64 * self.mag.len() - top.leading_zeros() as usize) as u32,
+            None => 0,
+        }
+    }
+
+    /// Bit i of self in two's complement
+    pub fn get_bit(&self, i: u32) -> bool {
+        let bit = |mag: &[u64]| {
+            mag.get(i as usize / 64)
+                .is_some_and(|l| l >> (i % 64) & 1 == 1)
+        };
+        if self.neg {
+            // -x = !(x - 1)
+            !bit(&sub_mag(&self.mag, &[1]))
+        } else {
+            bit(&self.mag)
+        }
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.mag.first().is_none_or(|l| l & 1 == 0)
+    }
+
+    pub fn is_odd(&self) -> bool {
+        !self.is_even()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match (self.neg, &self.mag[..]) {
+            (_, []) => Some(0),
+            (false, [l]) => Some(*l),
+            _ => None,
+        }
+    }
+
+    pub fn to_u32(&self) -> Option<u32> {
+        self.to_u64().and_then(|v| u32::try_from(v).ok())
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        match &self.mag[..] {
+            [] => Some(0),
+            [l] if self.neg => 0i64.checked_sub_unsigned(*l),
+            [l] => i64::try_from(*l).ok(),
+            _ => None,
+        }
+    }
+
+    /// Nearest f64 below |self| in magnitude, enough for estimates
+    pub fn to_f64(&self) -> f64 {
+        let m = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &l| acc * 18446744073709551616.0 + l as f64);
+        if self.neg {
+            -m
+        } else {
+            m
+        }
+    }
+
+    pub fn abs(mut self) -> Integer {
+        self.neg = false;
+        self
+    }
+
+    pub fn square_ref(&self) -> Integer {
+        Integer::from_parts(false, mul_mag(&self.mag, &self.mag))
+    }
+
+    pub fn square(self) -> Integer {
+        self.square_ref()
+    }
+
+    pub fn square_mut(&mut self) {
+        *self = self.square_ref();
+    }
+
+    /// (self / d, self % d), rounded toward zero
+    pub fn div_rem_ref(&self, d: &Integer) -> (Integer, Integer) {
+        let (q, r) = divrem_mag(&self.mag, &d.mag);
+        (
+            Integer::from_parts(self.neg != d.neg, q),
+            Integer::from_parts(self.neg, r),
+        )
+    }
+
+    /// (self / d, self % d), the quotient rounded down
+    pub fn div_rem_floor_ref(&self, d: &Integer) -> (Integer, Integer) {
+        let (q, r) = self.div_rem_ref(d);
+        if !r.is_zero() && r.neg != d.neg {
+            (q - 1, r + d)
+        } else {
+            (q, r)
+        }
+    }
+
+    /// self mod d in [0, d), d > 0
+    pub fn mod_u(&self, d: u32) -> u32 {
+        let r = divrem_limb(&self.mag, d as u64).1 as u32;
+        if self.neg && r != 0 {
+            d - r
+        } else {
+            r
+        }
+    }
+
+    /// self = self / d, d dividing self
+    pub fn div_exact_u_mut(&mut self, d: u32) {
+        let (q, r) = divrem_limb(&self.mag, d as u64);
+        debug_assert!(r == 0, "inexact division");
+        *self = Integer::from_parts(self.neg, q);
+    }
+
+    pub fn is_divisible(&self, d: &Integer) -> bool {
+        if d.is_zero() {
+            return self.is_zero();
+        }
+        divrem_mag(&self.mag, &d.mag).1.is_empty()
+    }
+
+    pub fn is_divisible_u(&self, d: u32) -> bool {
+        if d == 0 {
+            return self.is_zero();
+        }
+        divrem_limb(&self.mag, d as u64).1 == 0
+    }
+
+    /// Keeps the low bits of self, which is nonnegative
+    pub fn keep_bits_mut(&mut self, bits: u32) {
+        assert!(!self.neg, "keep_bits of a negative integer");
+        let (limbs, bits) = (bits as usize / 64, bits % 64);
+        if limbs < self.mag.len() {
+            self.mag.truncate(limbs + 1);
+            self.mag[limbs] &= (1u64 << bits) - 1;
+            self.mag = trim(std::mem::take(&mut self.mag));
+        }
+    }
+
+    pub fn gcd_ref(&self, other: &Integer) -> Integer {
+        let (mut a, mut b) = (self.mag.clone(), other.mag.clone());
+        while !b.is_empty() {
+            let r = divrem_mag(&a, &b).1;
+            a = std::mem::replace(&mut b, r);
+        }
+        Integer::from_parts(false, a)
+    }
+
+    pub fn gcd(self, other: &Integer) -> Integer {
+        self.gcd_ref(other)
+    }
+
+    /// Inverse of self mod m in [0, |m|), None if there is none
+    pub fn invert_ref(&self, m: &Integer) -> Option<Integer> {
+        let m = Integer::from_parts(false, m.mag.clone());
+        if m.is_zero() {
+            return None;
+        }
+        // r0 = s0 self mod m, r1 = s1 self mod m
+        let (mut r0, mut r1) = (m.clone(), self.div_rem_floor_ref(&m).1);
+        let (mut s0, mut s1) = (Integer::new(), Integer::from(1));
+        while !r1.is_zero() {
+            let (q, r) = r0.div_rem_ref(&r1);
+            let s = s0 - &q * &s1;
+            r0 = std::mem::replace(&mut r1, r);
+            s0 = std::mem::replace(&mut s1, s);
+        }
+        if r0 != 1 {
+            return if m == 1 { Some(Integer::new()) } else { None };
+        }
+        Some(s0.div_rem_floor_ref(&m).1)
+    }
+
+    pub fn invert(self, m: &Integer) -> Result<Integer, Integer> {
+        self.invert_ref(m).ok_or(self)
+    }
+
+    /// self^e mod m in [0, |m|), e negative meaning the inverse's power
+    pub fn pow_mod(self, e: &Integer, m: &Integer) -> Result<Integer, Integer> {
+        let base = if e.neg {
+            match self.invert_ref(m) {
+                Some(inv) => inv,
+                None => return Err(self),
+            }
+        } else {
+            self.div_rem_floor_ref(m).1
+        };
+        let mut r = Integer::from(1).div_rem_floor_ref(m).1;
+        for i in (0..e.significant_bits()).rev() {
+            r = r.square_ref().div_rem_floor_ref(m).1;
+            if e.mag[i as usize / 64] >> (i % 64) & 1 == 1 {
+                r = (&r * &base).div_rem_floor_ref(m).1;
+            }
+        }
+        Ok(r)
+    }
+
+    /// Miller-Rabin with the first reps primes as bases, after trial
+    /// division. Below 2^64 the first twelve primes prove primality.
+    pub fn is_probably_prime(&self, reps: u32) -> IsPrime {
+        const SMALL: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+        if self.neg || *self < 2 {
+            return IsPrime::No;
+        }
+        for p in SMALL {
+            if *self == p {
+                return IsPrime::Yes;
+            }
+            if self.is_divisible_u(p as u32) {
+                return IsPrime::No;
+            }
+        }
+        if let Some(n) = self.to_u64() {
+            let mul = |a: u64, b: u64| (a as u128 * b as u128 % n as u128) as u64;
+            let pow = |mut a: u64, mut e: u64| {
+                let mut r = 1;
+                while e > 0 {
+                    if e & 1 == 1 {
+                        r = mul(r, a);
+                    }
+                    a = mul(a, a);
+                    e >>= 1;
+                }
+                r
+            };
+            let s = (n - 1).trailing_zeros();
+            let d = (n - 1) >> s;
+            let composite = SMALL.iter().any(|&a| {
+                let mut x = pow(a, d);
+                if x == 1 || x == n - 1 {
+                    return false;
+                }
+                for _ in 1..s {
+                    x = mul(x, x);
+                    if x == n - 1 {
+                        return false;
+                    }
+                }
+                true
+            });
+            return if composite { IsPrime::No } else { IsPrime::Yes };
+        }
+        let n1: Integer = self - 1;
+        let s = (0..).find(|&i| n1.get_bit(i)).unwrap();
+        let d = &n1 >> s;
+        let bases = (2u32..)
+            .filter(|&a| (2..a).take_while(|b| b * b <= a).all(|b| a % b != 0))
+            .take(reps.max(1) as usize);
+        for a in bases {
+            let mut x = Integer::from(a).pow_mod(&d, self).unwrap();
+            if x == 1 || x == n1 {
+                continue;
+            }
+            let mut witness = true;
+            for _ in 1..s {
+                x = x.square_ref().div_rem_floor_ref(self).1;
+                if x == n1 {
+                    witness = false;
+                    break;
+                }
+            }
+            if witness {
+                return IsPrime::No;
+            }
+        }
+        IsPrime::Probably
+    }
+
+    /// Digits of |self|, without high zero digits
+    pub fn to_digits<T: Digit>(&self, order: Order) -> Vec<T> {
+        let mut digits: Vec<T> = self.mag.iter().map(|&l| T::from_limb(l)).collect();
+        if order == Order::Msf {
+            digits.reverse();
+        }
+        digits
+    }
+
+    /// Writes |self| into digits, which must be long enough, zero padded
+    pub fn write_digits<T: Digit>(&self, digits: &mut [T], order: Order) {
+        assert!(self.mag.len() <= digits.len(), "not enough digits");
+        for (i, d) in digits.iter_mut().enumerate() {
+            *d = T::from_limb(self.mag.get(i).copied().unwrap_or(0));
+        }
+        if order == Order::Msf {
+            digits.reverse();
+        }
+    }
+
+    pub fn from_digits<T: Digit>(digits: &[T], order: Order) -> Integer {
+        let mut mag: Vec<u64> = digits.iter().map(|d| d.to_limb()).collect();
+        if order == Order::Msf {
+            mag.reverse();
+        }
+        Integer::from_parts(false, mag)
+    }
+
+    pub fn significant_digits<T: Digit>(&self) -> usize {
+        self.mag.len()
+    }
+
+    pub fn from_str_radix(src: &str, radix: i32) -> Result<Integer, ParseIntegerError> {
+        assert!((2..=36).contains(&radix), "radix out of range");
+        let (neg, digits) = match src.as_bytes().first() {
+            Some(b'-') => (true, &src[1..]),
+            Some(b'+') => (false, &src[1..]),
+            _ => (false, src),
+        };
+        if !digits.starts_with(|c: char| c.is_digit(radix as u32)) {
+            return Err(ParseIntegerError);
+        }
+        let mut mag = vec![];
+        for c in digits.chars().filter(|&c| c != '_') {
+            let d = c.to_digit(radix as u32).ok_or(ParseIntegerError)?;
+            // mag = mag radix + d
+            let mut carry = d as u64;
+            for l in mag.iter_mut() {
+                let t = *l as u128 * radix as u128 + carry as u128;
+                *l = t as u64;
+                carry = (t >> 64) as u64;
+            }
+            if carry != 0 {
+                mag.push(carry);
+            }
+        }
+        Ok(Integer::from_parts(neg, mag))
+    }
+
+    /// Digits of |self| in radix, most significant first
+    fn radix_digits(&self, radix: u32, upper: bool) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+        // the largest power of radix in a limb
+        let (mut chunk, mut width) = (radix as u64, 1);
+        while let Some(c) = chunk.checked_mul(radix as u64) {
+            chunk = c;
+            width += 1;
+        }
+        let mut chunks = vec![];
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (q, r) = divrem_limb(&mag, chunk);
+            chunks.push(r);
+            mag = q;
+        }
+        let digit = |mut v: u64| {
+            let mut s = vec![];
+            for _ in 0..width {
+                let c = std::char::from_digit((v % radix as u64) as u32, radix).unwrap();
+                s.push(if upper { c.to_ascii_uppercase() } else { c });
+                v /= radix as u64;
+            }
+            s.into_iter().rev().collect::<String>()
+        };
+        let s: String = chunks.into_iter().rev().map(digit).collect();
+        s.trim_start_matches('0').to_string()
+    }
+}
+
+impl Pow<u32> for Integer {
+    type Output = Integer;
+
+    fn pow(self, mut e: u32) -> Integer {
+        let (mut base, mut r) = (self, Integer::from(1));
+        while e > 0 {
+            if e & 1 == 1 {
+                r *= &base;
+            }
+            e >>= 1;
+            if e > 0 {
+                base.square_mut();
+            }
+        }
+        r
+    }
+}
+
+impl Pow<u32> for &Integer {
+    type Output = Integer;
+
+    fn pow(self, e: u32) -> Integer {
+        self.clone().pow(e)
+    }
+}
+
+impl Ord for Integer {
+    fn cmp(&self, other: &Integer) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Integer) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn add_ref(a: &Integer, b: &Integer) -> Integer {
+    if a.neg == b.neg {
+        return Integer::from_parts(a.neg, add_mag(&a.mag, &b.mag));
+    }
+    match cmp_mag(&a.mag, &b.mag) {
+        Ordering::Less => Integer::from_parts(b.neg, sub_mag(&b.mag, &a.mag)),
+        _ => Integer::from_parts(a.neg, sub_mag(&a.mag, &b.mag)),
+    }
+}
+
+fn sub_ref(a: &Integer, b: &Integer) -> Integer {
+    if b.is_zero() {
+        return a.clone();
+    }
+    let neg_b = Integer {
+        neg: !b.neg,
+        mag: b.mag.clone(),
+    };
+    add_ref(a, &neg_b)
+}
+
+fn mul_ref(a: &Integer, b: &Integer) -> Integer {
+    Integer::from_parts(a.neg != b.neg, mul_mag(&a.mag, &b.mag))
+}
+
+fn div_ref(a: &Integer, b: &Integer) -> Integer {
+    a.div_rem_ref(b).0
+}
+
+fn rem_ref(a: &Integer, b: &Integer) -> Integer {
+    a.div_rem_ref(b).1
+}
+
+/// Only for nonnegative operands
+fn bitor_ref(a: &Integer, b: &Integer) -> Integer {
+    assert!(!a.neg && !b.neg, "bitwise or of a negative integer");
+    let (a, b) = if a.mag.len() >= b.mag.len() {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    let mut mag = a.mag.clone();
+    for (l, &x) in mag.iter_mut().zip(&b.mag) {
+        *l |= x;
+    }
+    Integer::from_parts(false, mag)
+}
+
+macro_rules! binop {
+    ($Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident, $f:ident) => {
+        impl $Op<&Integer> for &Integer {
+            type Output = Integer;
+            fn $op(self, b: &Integer) -> Integer {
+                $f(self, b)
+            }
+        }
+        impl $Op<Integer> for &Integer {
+            type Output = Integer;
+            fn $op(self, b: Integer) -> Integer {
+                $f(self, &b)
+            }
+        }
+        impl $Op<&Integer> for Integer {
+            type Output = Integer;
+            fn $op(self, b: &Integer) -> Integer {
+                $f(&self, b)
+            }
+        }
+        impl $Op<Integer> for Integer {
+            type Output = Integer;
+            fn $op(self, b: Integer) -> Integer {
+                $f(&self, &b)
+            }
+        }
+        impl $OpAssign<&Integer> for Integer {
+            fn $op_assign(&mut self, b: &Integer) {
+                *self = $f(self, b);
+            }
+        }
+        impl $OpAssign<Integer> for Integer {
+            fn $op_assign(&mut self, b: Integer) {
+                *self = $f(self, &b);
+            }
+        }
+        binop!(@prim $Op, $op, $OpAssign, $op_assign, $f; i32 i64 u32 u64 usize);
+    };
+    (@prim $Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident, $f:ident; $($t:ty)*) => {
+        $(
+            impl $Op<$t> for &Integer {
+                type Output = Integer;
+                fn $op(self, b: $t) -> Integer {
+                    $f(self, &Integer::from(b))
+                }
+            }
+            impl $Op<$t> for Integer {
+                type Output = Integer;
+                fn $op(self, b: $t) -> Integer {
+                    $f(&self, &Integer::from(b))
+                }
+            }
+            impl $Op<&Integer> for $t {
+                type Output = Integer;
+                fn $op(self, b: &Integer) -> Integer {
+                    $f(&Integer::from(self), b)
+                }
+            }
+            impl $Op<Integer> for $t {
+                type Output = Integer;
+                fn $op(self, b: Integer) -> Integer {
+                    $f(&Integer::from(self), &b)
+                }
+            }
+            impl $OpAssign<$t> for Integer {
+                fn $op_assign(&mut self, b: $t) {
+                    *self = $f(self, &Integer::from(b));
+                }
+            }
+        )*
+    };
+}
+
+binop!(Add, add, AddAssign, add_assign, add_ref);
+binop!(Sub, sub, SubAssign, sub_assign, sub_ref);
+binop!(Mul, mul, MulAssign, mul_assign, mul_ref);
+binop!(Div, div, DivAssign, div_assign, div_ref);
+binop!(Rem, rem, RemAssign, rem_assign, rem_ref);
+
+impl BitOr<&Integer> for Integer {
+    type Output = Integer;
+    fn bitor(self, b: &Integer) -> Integer {
+        bitor_ref(&self, b)
+    }
+}
+
+impl BitOr<Integer> for Integer {
+    type Output = Integer;
+    fn bitor(self, b: Integer) -> Integer {
+        bitor_ref(&self, &b)
+    }
+}
+
+impl BitOr<i32> for Integer {
+    type Output = Integer;
+    fn bitor(self, b: i32) -> Integer {
+        bitor_ref(&self, &Integer::from(b))
+    }
+}
+
+impl Neg for Integer {
+    type Output = Integer;
+    fn neg(self) -> Integer {
+        Integer::from_parts(!self.neg, self.mag)
+    }
+}
+
+impl Neg for &Integer {
+    type Output = Integer;
+    fn neg(self) -> Integer {
+        -self.clone()
+    }
+}
+
+/// self << bits
+fn shl_ref(a: &Integer, bits: usize) -> Integer {
+    Integer::from_parts(a.neg, shl_mag(&a.mag, bits))
+}
+
+/// self >> bits, rounded down as an arithmetic shift
+fn shr_ref(a: &Integer, bits: usize) -> Integer {
+    if a.neg {
+        // -x >> b = -((x - 1) >> b) - 1
+        let m = shr_mag(&sub_mag(&a.mag, &[1]), bits);
+        Integer::from_parts(true, add_mag(&m, &[1]))
+    } else {
+        Integer::from_parts(false, shr_mag(&a.mag, bits))
+    }
+}
+
+macro_rules! shift {
+    ($($t:ty)*) => {
+        $(
+            impl Shl<$t> for Integer {
+                type Output = Integer;
+                fn shl(self, bits: $t) -> Integer {
+                    shl_ref(&self, bits as usize)
+                }
+            }
+            impl Shl<$t> for &Integer {
+                type Output = Integer;
+                fn shl(self, bits: $t) -> Integer {
+                    shl_ref(self, bits as usize)
+                }
+            }
+            impl ShlAssign<$t> for Integer {
+                fn shl_assign(&mut self, bits: $t) {
+                    *self = shl_ref(self, bits as usize);
+                }
+            }
+            impl Shr<$t> for Integer {
+                type Output = Integer;
+                fn shr(self, bits: $t) -> Integer {
+                    shr_ref(&self, bits as usize)
+                }
+            }
+            impl Shr<$t> for &Integer {
+                type Output = Integer;
+                fn shr(self, bits: $t) -> Integer {
+                    shr_ref(self, bits as usize)
+                }
+            }
+            impl ShrAssign<$t> for Integer {
+                fn shr_assign(&mut self, bits: $t) {
+                    *self = shr_ref(self, bits as usize);
+                }
+            }
+        )*
+    };
+}
+
+shift!(u32 i32 usize);
+
+impl Integer {
+    /// self compared with the integer of sign neg and magnitude m,
+    /// without allocating
+    fn cmp_parts(&self, neg: bool, m: u128) -> Ordering {
+        let other_neg = neg && m != 0;
+        let m = [m as u64, (m >> 64) as u64];
+        match (self.neg, other_neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &m),
+            (true, true) => cmp_mag(&m, &self.mag),
+        }
+    }
+}
+
+macro_rules! from_prim {
+    ($via:ident, $parts:expr; $($t:ty)*) => {
+        $(
+            impl From<$t> for Integer {
+                fn from(v: $t) -> Integer {
+                    Integer::$via(v as _)
+                }
+            }
+            impl PartialEq<$t> for Integer {
+                fn eq(&self, other: &$t) -> bool {
+                    let (neg, m) = $parts(*other as _);
+                    self.cmp_parts(neg, m) == Ordering::Equal
+                }
+            }
+            impl PartialEq<Integer> for $t {
+                fn eq(&self, other: &Integer) -> bool {
+                    other == self
+                }
+            }
+            impl PartialOrd<$t> for Integer {
+                fn partial_cmp(&self, other: &$t) -> Option<Ordering> {
+                    let (neg, m) = $parts(*other as _);
+                    Some(self.cmp_parts(neg, m))
+                }
+            }
+            impl PartialOrd<Integer> for $t {
+                fn partial_cmp(&self, other: &Integer) -> Option<Ordering> {
+                    other.partial_cmp(self).map(Ordering::reverse)
+                }
+            }
+            impl Assign<$t> for Integer {
+                fn assign(&mut self, src: $t) {
+                    *self = Integer::from(src);
+                }
+            }
+        )*
+    };
+}
+
+from_prim!(from_i128, |v: i128| (v < 0, v.unsigned_abs()); i32 i64 isize i128);
+from_prim!(from_u128, |v: u128| (false, v); u32 u64 usize u128);
+
+impl From<&Integer> for Integer {
+    fn from(v: &Integer) -> Integer {
+        v.clone()
+    }
+}
+
+impl Assign<Integer> for Integer {
+    fn assign(&mut self, src: Integer) {
+        *self = src;
+    }
+}
+
+impl Assign<&Integer> for Integer {
+    fn assign(&mut self, src: &Integer) {
+        self.clone_from(src);
+    }
+}
+
+impl Assign<(Integer, Integer)> for (&mut Integer, &mut Integer) {
+    fn assign(&mut self, src: (Integer, Integer)) {
+        *self.0 = src.0;
+        *self.1 = src.1;
+    }
+}
+
+impl FromStr for Integer {
+    type Err = ParseIntegerError;
+
+    fn from_str(s: &str) -> Result<Integer, ParseIntegerError> {
+        Integer::from_str_radix(s, 10)
+    }
+}
+
+macro_rules! format_radix {
+    ($($Trait:ident $radix:literal $upper:literal $prefix:literal,)*) => {
+        $(
+            impl fmt::$Trait for Integer {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.pad_integral(!self.neg, $prefix, &self.radix_digits($radix, $upper))
+                }
+            }
+        )*
+    };
+}
+
+format_radix! {
+    Display 10 false "",
+    Binary 2 false "0b",
+    Octal 8 false "0o",
+    LowerHex 16 false "0x",
+    UpperHex 16 true "0x",
+}
+
+impl fmt::Debug for Integer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::iter::Sum for Integer {
+    fn sum<I: Iterator<Item = Integer>>(iter: I) -> Integer {
+        iter.fold(Integer::new(), |acc, x| acc + x)
+    }
+}
+
+impl std::iter::Product for Integer {
+    fn product<I: Iterator<Item = Integer>>(iter: I) -> Integer {
+        iter.fold(Integer::from(1), |acc, x| acc * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Random integers of up to 80 limbs, a quarter of them negative
+    fn random(rand: &mut RandState) -> Integer {
+        let limbs = [1, 2, 3, 31, 32, 33, 70, 80][rand.below(8) as usize];
+        let bits = 64 * limbs - rand.below(64);
+        let a = Integer::random_bits(bits, rand);
+        if rand.below(4) == 0 {
+            -a
+        } else {
+            a
+        }
+    }
+
+    #[test]
+    fn bignum_arithmetic_tests() {
+        let mut rand = RandState::new();
+        for _ in 0..300 {
+            let (a, b) = (random(&mut rand), random(&mut rand));
+            assert_eq!(&a + &b - &b, a);
+            assert_eq!(
+                mul_mag(&a.mag, &b.mag),
+                trim(schoolbook(&a.mag, &b.mag)),
+                "Karatsuba"
+            );
+            let ab = &a * &b;
+            if b != 0 {
+                assert_eq!(ab.div_rem_ref(&b), (a.clone(), Integer::new()));
+                let (q, r) = a.div_rem_ref(&b);
+                assert_eq!(&q * &b + &r, a);
+                assert!(r.clone().abs() < b.clone().abs() && (r == 0 || r.neg == a.neg));
+                let (q, r) = a.div_rem_floor_ref(&b);
+                assert_eq!(&q * &b + &r, a);
+                assert!(r == 0 || r.neg == b.neg);
+            }
+            let k = rand.below(200);
+            assert_eq!((&a << k) >> k, a);
+            let floor = a.div_rem_floor_ref(&(Integer::from(1) << k)).0;
+            assert_eq!(&a >> k, floor);
+            assert_eq!(a.to_string().parse::<Integer>(), Ok(a.clone()));
+            let hex = format!("{:x}", a);
+            assert_eq!(Integer::from_str_radix(&hex, 16), Ok(a.clone()));
+        }
+        assert_eq!(
+            Integer::from(-7).div_rem_ref(&Integer::from(2)),
+            (Integer::from(-3), Integer::from(-1))
+        );
+        assert_eq!(Integer::from(-7) >> 1, -4);
+        assert_eq!(Integer::from(-7).mod_u(3), 2);
+        let (m7, m1, max) = (
+            Integer::from(-7),
+            Integer::from(-1),
+            Integer::from(u64::MAX),
+        );
+        assert!(m7 < 0 && m7 < m1 && m1 < -0 && 0 < max && max == u64::MAX);
+        assert_eq!(
+            format!("{:#x} {:b}", Integer::from(255), Integer::from(-5)),
+            "0xff -101"
+        );
+        assert_eq!(
+            Integer::u_pow_u(10, 30).to_string(),
+            format!("1{}", "0".repeat(30))
+        );
+        assert!("12a".parse::<Integer>().is_err() && "".parse::<Integer>().is_err());
+    }
+
+    #[test]
+    fn bignum_number_theory_tests() {
+        let mersenne = |e: u32| -> Integer { Integer::u_pow_u(2, e) - 1 };
+        for e in [61, 89, 107, 127, 521] {
+            let p = mersenne(e);
+            assert_ne!(p.is_probably_prime(30), IsPrime::No, "2^{} - 1", e);
+            // Fermat
+            let a = Integer::from(123456789);
+            assert_eq!(a.clone().pow_mod(&(&p - 1), &p), Ok(Integer::from(1)));
+            let inv = a.invert_ref(&p).unwrap();
+            assert_eq!(&a * &inv % &p, 1);
+        }
+        for e in [67, 101, 128] {
+            assert_eq!(
+                mersenne(e).is_probably_prime(30),
+                IsPrime::No,
+                "2^{} - 1",
+                e
+            );
+        }
+        // the largest prime below 2^64, a Carmichael number and a strong
+        // pseudoprime to the bases 2, 3, 5 and 7
+        assert_eq!(
+            Integer::from(18446744073709551557u64).is_probably_prime(30),
+            IsPrime::Yes
+        );
+        assert_eq!(Integer::from(561).is_probably_prime(30), IsPrime::No);
+        assert_eq!(
+            Integer::from(3215031751u64).is_probably_prime(30),
+            IsPrime::No
+        );
+
+        let (p, q) = (mersenne(61), mersenne(89));
+        let n = &p * &q;
+        assert_eq!((&p * 12345u32).gcd(&n), p);
+        assert_eq!((&q * 2u32).invert(&n), Err(&q * 2u32));
+        assert_eq!(Integer::from(-1).invert(&n), Ok(&n - 1));
+    }
+
+    /// Every operation against rug on the same operands
+    #[cfg(feature = "gmp")]
+    #[test]
+    fn bignum_rug_cross_check() {
+        let rug = |a: &Integer| a.to_string().parse::<rug::Integer>().unwrap();
+        let mut rand = RandState::new();
+        for _ in 0..300 {
+            let (a, b) = (random(&mut rand), random(&mut rand));
+            let (ra, rb) = (rug(&a), rug(&b));
+            assert_eq!(rug(&(&a * &b)), rug::Integer::from(&ra * &rb));
+            assert_eq!(rug(&(&a - &b)), rug::Integer::from(&ra - &rb));
+            assert_eq!(rug(&a.gcd_ref(&b)), rug::Integer::from(ra.gcd_ref(&rb)));
+            if b != 0 {
+                let (q, r) = a.div_rem_ref(&b);
+                let (rq, rr) = <(rug::Integer, rug::Integer)>::from(ra.div_rem_ref(&rb));
+                assert_eq!((rug(&q), rug(&r)), (rq, rr));
+                assert_eq!(
+                    a.invert_ref(&b).map(|i| rug(&i)),
+                    ra.invert_ref(&rb).map(rug::Integer::from)
+                );
+            }
+            assert_eq!(a.significant_bits(), ra.significant_bits());
+            assert_eq!(a.get_bit(70), ra.get_bit(70));
+            assert_eq!(format!("{:#x}", a), format!("{:#x}", ra));
+            let m = rug(&b).abs() | 1u32;
+            let e = rand.below(1000);
+            assert_eq!(
+                rug(&a
+                    .clone()
+                    .pow_mod(&Integer::from(e), &m.to_string().parse().unwrap())
+                    .unwrap()),
+                ra.clone().pow_mod(&rug::Integer::from(e), &m).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,633 @@
+use crate::arith::integer::Integer;
+use crate::arith::montgomery_point::{FullCurve, FullPoint, MontgomeryCurve, MontgomeryPoint};
+use crate::arith::ring::ModularRing;
+use std::cell::RefCell;
+
+/// The coefficient a of a twisted Edwards curve ax^2 + y^2 = 1 + dx^2y^2
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Twist {
+    /// a = -1, for which the group operations are the cheapest
+    MinusOne,
+    /// a = 1, the untwisted curves, among which are the families with
+    /// rational torsion Z/12 and Z/2 x Z/8
+    One,
+}
+
+/// Twisted Edwards curve ax^2 + y^2 = 1 + dx^2y^2 mod n with a = -1 or
+/// a = 1. Points are kept in extended coordinates (X:Y:Z:T), with
+/// x = X/Z, y = Y/Z and T = XY/Z, for which the formulas of Hisil, Wong,
+/// Carter and Dawson ("Twisted Edwards Curves Revisited", 2008) apply.
+///
+/// The curve is birationally equivalent to the Montgomery curve
+/// By^2 = x^3 + Ax^2 + x with A = 2(a + d)/(a - d) and B = a(A + 2),
+/// that is a24 = a/(a - d), through x = u/v, y = (u - 1)/(u + 1).
+///
+/// As for `FullCurve`, the curve owns the scratch residues of the group
+/// operations and is meant to be used by the thread that built it.
+#[derive(Clone, Debug)]
+pub struct EdwardsCurve<R: ModularRing> {
+    ring: R,
+    twist: Twist,
+    d: R::Elem,
+    /// The constant of the addition formulas: 2d for a = -1, d for a = 1
+    k: R::Elem,
+    scratch: RefCell<[R::Elem; 8]>,
+}
+
+/// Point (X:Y:Z:T) on an `EdwardsCurve`, the neutral element being
+/// (0:1:1:0)
+#[derive(Clone, PartialEq, Debug)]
+pub struct EdwardsPoint<R: ModularRing> {
+    pub x: R::Elem,
+    pub y: R::Elem,
+    pub z: R::Elem,
+    pub t: R::Elem,
+}
+
+impl<R: ModularRing> EdwardsCurve<R> {
+    /// Edwards curve given by a and d mod modulo
+    pub fn new(twist: Twist, d: Integer, modulo: Integer) -> EdwardsCurve<R> {
+        let ring = R::new(&modulo).unwrap();
+        EdwardsCurve::with_ring(twist, ring.to_residue(&d), ring)
+    }
+
+    /// Edwards curve whose d is already a residue of ring
+    pub fn with_ring(twist: Twist, d: R::Elem, ring: R) -> EdwardsCurve<R> {
+        let k = match twist {
+            Twist::MinusOne => ring.add(&d, &d),
+            Twist::One => d.clone(),
+        };
+        let scratch = RefCell::new([(); 8].map(|_| ring.zero()));
+        EdwardsCurve {
+            ring,
+            twist,
+            d,
+            k,
+            scratch,
+        }
+    }
+
+    /// The curve of coefficient a equivalent to the Montgomery curve
+    /// By^2 = x^3 + Ax^2 + x with B = a(A + 2), and the image of its
+    /// affine point (u, v). Fails with the gcd with n of what is not
+    /// invertible: a24, or v(u + 1) for points of order 2 or 4 modulo a
+    /// prime of n.
+    pub fn from_montgomery(
+        twist: Twist,
+        curve: &MontgomeryCurve<'_, R>,
+        u: &Integer,
+        v: &Integer,
+    ) -> Result<(EdwardsCurve<R>, EdwardsPoint<R>), Integer> {
+        let ring = curve.ring();
+        // d = a(A - 2)/(A + 2) = a(a24 - 1)/a24
+        let inv = ring
+            .invert(curve.a24())
+            .ok_or_else(|| ring.gcd(curve.a24()))?;
+        let d = match twist {
+            Twist::MinusOne => ring.sub(&ring.one(), curve.a24()),
+            Twist::One => ring.sub(curve.a24(), &ring.one()),
+        };
+        let d = ring.mul(&d, &inv);
+        let (u, v) = (ring.to_residue(u), ring.to_residue(v));
+        let u1 = ring.add(&u, &ring.one());
+        let point = EdwardsPoint {
+            x: ring.mul(&u, &u1),
+            y: ring.mul(&ring.sub(&u, &ring.one()), &v),
+            z: ring.mul(&v, &u1),
+            t: ring.mul(&u, &ring.sub(&u, &ring.one())),
+        };
+        let g = ring.gcd(&point.z);
+        if g != 1 {
+            return Err(g);
+        }
+        Ok((EdwardsCurve::with_ring(twist, d, ring.clone()), point))
+    }
+
+    /// a24 = a/(a - d) of the Montgomery curve, or the gcd of a - d with n
+    pub fn montgomery_a24(&self) -> Result<R::Elem, Integer> {
+        let ring = &self.ring;
+        // 1/(1 + d) for a = -1, 1/(1 - d) for a = 1
+        let den = match self.twist {
+            Twist::MinusOne => ring.add(&self.d, &ring.one()),
+            Twist::One => ring.sub(&ring.one(), &self.d),
+        };
+        ring.invert(&den).ok_or_else(|| ring.gcd(&den))
+    }
+
+    /// The Montgomery curve with B = a(A + 2) of `from_montgomery`, and
+    /// the image (u, v) = ((1 + y)/(1 - y), u/x) of p, that is
+    /// ((Z + Y)X : (Z + Y)Z : (Z - Y)X). Fails with the gcd with n of
+    /// a - d, or of (Z - Y)X, zero for x = 0 or y = 1 mod a prime of n.
+    pub fn to_full_montgomery(
+        &self,
+        p: &EdwardsPoint<R>,
+    ) -> Result<(FullCurve<R>, FullPoint<R>), Integer> {
+        let ring = &self.ring;
+        let a24 = self.montgomery_a24()?;
+        // A = 4 a24 - 2, B = a(A + 2) = 4a a24
+        let two = ring.add(&ring.one(), &ring.one());
+        let a24_4 = ring.mul(&ring.add(&two, &two), &a24);
+        let b = match self.twist {
+            Twist::MinusOne => ring.sub(&ring.zero(), &a24_4),
+            Twist::One => a24_4.clone(),
+        };
+        let zy = ring.add(&p.z, &p.y);
+        let point = FullPoint {
+            x: ring.mul(&zy, &p.x),
+            y: ring.mul(&zy, &p.z),
+            z: ring.mul(&ring.sub(&p.z, &p.y), &p.x),
+        };
+        let g = ring.gcd(&point.z);
+        if g != 1 {
+            return Err(g);
+        }
+        let a = ring.sub(&a24_4, &two);
+        Ok((FullCurve::with_ring(a, b, ring.clone()), point))
+    }
+
+    pub fn ring(&self) -> &R {
+        &self.ring
+    }
+
+    pub fn twist(&self) -> Twist {
+        self.twist
+    }
+
+    pub fn d(&self) -> &R::Elem {
+        &self.d
+    }
+
+    /// Affine point (x, y) on this curve
+    pub fn point(&self, x: &Integer, y: &Integer) -> EdwardsPoint<R> {
+        let ring = &self.ring;
+        let (x, y) = (ring.to_residue(x), ring.to_residue(y));
+        EdwardsPoint {
+            t: ring.mul(&x, &y),
+            x,
+            y,
+            z: ring.one(),
+        }
+    }
+
+    /// The neutral element (0:1:1:0)
+    pub fn neutral(&self) -> EdwardsPoint<R> {
+        let ring = &self.ring;
+        EdwardsPoint {
+            x: ring.zero(),
+            y: ring.one(),
+            z: ring.one(),
+            t: ring.zero(),
+        }
+    }
+
+    /// aX^2 in r
+    fn scale_a(&self, r: &mut R::Elem, x2: &R::Elem) {
+        match self.twist {
+            Twist::MinusOne => self.ring.sub_into(r, &self.ring.zero(), x2),
+            Twist::One => *r = x2.clone(),
+        }
+    }
+
+    /// Whether (aX^2 + Y^2) Z^2 = Z^4 + d X^2 Y^2 and XY = ZT
+    pub fn is_on_curve(&self, p: &EdwardsPoint<R>) -> bool {
+        let ring = &self.ring;
+        let x2 = ring.square(&p.x);
+        let y2 = ring.square(&p.y);
+        let z2 = ring.square(&p.z);
+        let mut ax2 = ring.zero();
+        self.scale_a(&mut ax2, &x2);
+        let lhs = ring.mul(&ring.add(&ax2, &y2), &z2);
+        let mut rhs = ring.mul(&ring.mul(&self.d, &x2), &y2);
+        ring.add_assign(&mut rhs, &ring.square(&z2));
+        lhs == rhs && ring.mul(&p.x, &p.y) == ring.mul(&p.z, &p.t)
+    }
+
+    /// -p = (-X:Y:Z:-T)
+    pub fn neg(&self, p: &EdwardsPoint<R>) -> EdwardsPoint<R> {
+        let ring = &self.ring;
+        EdwardsPoint {
+            x: ring.sub(&ring.zero(), &p.x),
+            y: p.y.clone(),
+            z: p.z.clone(),
+            t: ring.sub(&ring.zero(), &p.t),
+        }
+    }
+
+    /// The end of an addition, from C = k T1 T2, D = 2 Z1 Z2 (a = -1) or
+    /// Z1 Z2 (a = 1), E and H in t[2], t[3], t[4] and t[7]
+    fn finish_add(&self, t: &mut [R::Elem; 8], out: &mut EdwardsPoint<R>) {
+        let ring = &self.ring;
+        let [_, _, c, d, e, f, g, h] = t;
+        ring.sub_into(f, d, c);
+        ring.add_into(g, d, c);
+        ring.mul_into(&mut out.x, e, f);
+        ring.mul_into(&mut out.y, g, h);
+        ring.mul_into(&mut out.t, e, h);
+        ring.mul_into(&mut out.z, f, g);
+    }
+
+    /// Leaves C, E and H of an addition of p and q in t[2], t[4] and t[7]:
+    /// for a = -1, A = (Y1 - X1)(Y2 - X2), B = (Y1 + X1)(Y2 + X2),
+    /// E = B - A and H = B + A; for a = 1, A = X1 X2, B = Y1 Y2,
+    /// E = (X1 + Y1)(X2 + Y2) - A - B and H = B - A
+    fn add_start(&self, t: &mut [R::Elem; 8], p: &EdwardsPoint<R>, q: &EdwardsPoint<R>) {
+        let ring = &self.ring;
+        let [a, b, c, _, e, _, _, h] = t;
+        match self.twist {
+            Twist::MinusOne => {
+                ring.sub_into(a, &p.y, &p.x);
+                ring.sub_into(e, &q.y, &q.x);
+                ring.mul_assign(a, e);
+                ring.add_into(b, &p.y, &p.x);
+                ring.add_into(e, &q.y, &q.x);
+                ring.mul_assign(b, e);
+                ring.sub_into(e, b, a);
+                ring.add_into(h, b, a);
+            }
+            Twist::One => {
+                ring.mul_into(a, &p.x, &q.x);
+                ring.mul_into(b, &p.y, &q.y);
+                ring.add_into(e, &p.x, &p.y);
+                ring.add_into(h, &q.x, &q.y);
+                ring.mul_assign(e, h);
+                ring.sub_assign(e, a);
+                ring.sub_assign(e, b);
+                ring.sub_into(h, b, a);
+            }
+        }
+        ring.mul_into(c, &p.t, &self.k);
+        ring.mul_assign(c, &q.t);
+    }
+
+    /// D = 2 z for a = -1, z for a = 1
+    fn scale_d(&self, d: &mut R::Elem, z: &R::Elem) {
+        match self.twist {
+            Twist::MinusOne => self.ring.add_into(d, z, z),
+            Twist::One => *d = z.clone(),
+        }
+    }
+
+    /// out = p + q, valid for p = q as well, requires nine multiplications
+    /// for a = -1 and ten for a = 1
+    pub fn add_into(&self, out: &mut EdwardsPoint<R>, p: &EdwardsPoint<R>, q: &EdwardsPoint<R>) {
+        let ring = &self.ring;
+        let t = &mut *self.scratch.borrow_mut();
+        self.add_start(t, p, q);
+        let [_, _, _, d, _, f, ..] = &mut *t;
+        ring.mul_into(f, &p.z, &q.z);
+        self.scale_d(d, f);
+        self.finish_add(t, out);
+    }
+
+    /// out = p + q for q with Z = 1, one multiplication less than
+    /// `add_into`
+    pub fn add_mixed_into(
+        &self,
+        out: &mut EdwardsPoint<R>,
+        p: &EdwardsPoint<R>,
+        q: &EdwardsPoint<R>,
+    ) {
+        debug_assert!(q.z == self.ring.one(), "q is not normalized");
+        let t = &mut *self.scratch.borrow_mut();
+        self.add_start(t, p, q);
+        self.scale_d(&mut t[3], &p.z);
+        self.finish_add(t, out);
+    }
+
+    /// out = [2]p, in four multiplications and four squarings, or three
+    /// multiplications if T is not needed (when the next operation is a
+    /// doubling too), in which case out.t is left as is
+    fn double_with(&self, out: &mut EdwardsPoint<R>, p: &EdwardsPoint<R>, extended: bool) {
+        let ring = &self.ring;
+        let t = &mut *self.scratch.borrow_mut();
+        let [a, b, c, e, f, g, h, _] = t;
+        // A = X^2, B = Y^2, C = 2Z^2, E = (X + Y)^2 - A - B
+        ring.square_into(a, &p.x);
+        ring.square_into(b, &p.y);
+        ring.square_into(h, &p.z);
+        ring.add_into(c, h, h);
+        ring.add_into(e, &p.x, &p.y);
+        ring.square_assign(e);
+        ring.sub_assign(e, a);
+        ring.sub_assign(e, b);
+        match self.twist {
+            // G = B - A, and -F = C - G, -H = A + B: the signs cancel out
+            Twist::MinusOne => {
+                ring.sub_into(g, b, a);
+                ring.sub_into(f, c, g);
+                ring.add_into(h, a, b);
+            }
+            // G = A + B, F = G - C, H = A - B
+            Twist::One => {
+                ring.add_into(g, a, b);
+                ring.sub_into(f, g, c);
+                ring.sub_into(h, a, b);
+            }
+        }
+        ring.mul_into(&mut out.x, e, f);
+        ring.mul_into(&mut out.y, g, h);
+        ring.mul_into(&mut out.z, f, g);
+        if extended {
+            ring.mul_into(&mut out.t, e, h);
+        }
+    }
+
+    /// out = [2]p
+    pub fn double_into(&self, out: &mut EdwardsPoint<R>, p: &EdwardsPoint<R>) {
+        self.double_with(out, p, true);
+    }
+
+    /// out = [3]p, requires eleven multiplications and three squarings
+    pub fn triple_into(&self, out: &mut EdwardsPoint<R>, p: &EdwardsPoint<R>) {
+        let ring = &self.ring;
+        let t = &mut *self.scratch.borrow_mut();
+        let [xx, yy, b, ap, xb, yb, aa, tmp] = t;
+        // XX = aX^2, Ap = YY + XX, B = 2(2Z^2 - Ap)
+        ring.square_into(tmp, &p.x);
+        self.scale_a(xx, tmp);
+        ring.square_into(yy, &p.y);
+        ring.add_into(ap, yy, xx);
+        ring.square_into(tmp, &p.z);
+        ring.add_into(b, tmp, tmp);
+        ring.sub_assign(b, ap);
+        ring.add_into(tmp, b, b);
+        std::mem::swap(b, tmp);
+        // xB = XX B, yB = YY B, AA = Ap (YY - XX)
+        ring.mul_into(xb, xx, b);
+        ring.mul_into(yb, yy, b);
+        ring.sub_into(tmp, yy, xx);
+        ring.mul_into(aa, ap, tmp);
+        // xE = X (yB + AA), yH = Y (xB - AA), zF = Z (AA - yB) and
+        // zG = Z (AA + xB)
+        ring.add_into(xx, yb, aa);
+        ring.mul_assign(xx, &p.x);
+        ring.sub_into(yy, xb, aa);
+        ring.mul_assign(yy, &p.y);
+        ring.sub_into(b, aa, yb);
+        ring.mul_assign(b, &p.z);
+        ring.add_into(ap, aa, xb);
+        ring.mul_assign(ap, &p.z);
+        ring.mul_into(&mut out.x, xx, b);
+        ring.mul_into(&mut out.y, yy, ap);
+        ring.mul_into(&mut out.z, b, ap);
+        ring.mul_into(&mut out.t, xx, yy);
+    }
+
+    /// [k]p for k >= 1, by signed sliding windows: the digits of k in
+    /// width-w NAF are odd and in (-2^(w-1), 2^(w-1)), so that only the
+    /// odd multiples of p up to 2^(w-1) are precomputed, and one addition
+    /// is done per w bits of k on average.
+    ///
+    /// The odd multiples are normalized to Z = 1 with one inversion, for
+    /// mixed additions. If that inversion fails, some multiple is zero
+    /// mod a prime of n, and the additions stay projective.
+    pub fn mul(&self, p: &EdwardsPoint<R>, k: &Integer) -> EdwardsPoint<R> {
+        assert!(*k >= 1, "k must be positive");
+        let ring = &self.ring;
+        let w = match k.significant_bits() {
+            0..=16 => 2,
+            17..=64 => 3,
+            65..=512 => 4,
+            513..=4096 => 5,
+            _ => 6,
+        };
+        let digits = naf(k, w);
+        // odd[i] = [2i + 1]p and its opposite, [3]p by a tripling, which
+        // is cheaper than a doubling and an addition
+        let mut odd = vec![p.clone()];
+        if w > 2 {
+            let mut p3 = p.clone();
+            self.triple_into(&mut p3, p);
+            odd.push(p3);
+            let mut p2 = p.clone();
+            if w > 3 {
+                self.double_into(&mut p2, p);
+            }
+            for i in 2..1 << (w - 2) {
+                let mut q = p.clone();
+                self.add_into(&mut q, &odd[i - 1], &p2);
+                odd.push(q);
+            }
+        }
+        let mut inverses: Vec<_> = odd.iter().map(|q| q.z.clone()).collect();
+        let mixed = ring.batch_invert(&mut inverses).is_ok();
+        if mixed {
+            for (q, z) in odd.iter_mut().zip(inverses.iter()) {
+                ring.mul_assign(&mut q.x, z);
+                ring.mul_assign(&mut q.y, z);
+                ring.mul_assign(&mut q.t, z);
+                q.z = ring.one();
+            }
+        }
+        let negated: Vec<_> = odd.iter().map(|q| self.neg(q)).collect();
+
+        let top = *digits.last().unwrap();
+        let mut q = odd[(top / 2) as usize].clone();
+        let mut tmp = q.clone();
+        for (i, &digit) in digits.iter().enumerate().rev().skip(1) {
+            // T is only needed by additions, and in the result
+            self.double_with(&mut tmp, &q, digit != 0 || i == 0);
+            std::mem::swap(&mut q, &mut tmp);
+            let r = match digit {
+                0 => continue,
+                d if d > 0 => &odd[(d / 2) as usize],
+                d => &negated[(-d / 2) as usize],
+            };
+            if mixed {
+                self.add_mixed_into(&mut tmp, &q, r);
+            } else {
+                self.add_into(&mut tmp, &q, r);
+            }
+            std::mem::swap(&mut q, &mut tmp);
+        }
+        q
+    }
+}
+
+/// Width-w NAF of k > 0, least significant digit first
+fn naf(k: &Integer, w: u32) -> Vec<i64> {
+    let mut k = Integer::from(k);
+    let mut digits = Vec::with_capacity(k.significant_bits() as usize + 1);
+    let window = 1i64 << w;
+    while k > 0 {
+        let digit = if k.is_odd() {
+            let mut d = k.mod_u(window as u32) as i64;
+            if d >= window / 2 {
+                d -= window;
+            }
+            k -= d;
+            d
+        } else {
+            0
+        };
+        digits.push(digit);
+        k >>= 1;
+    }
+    digits
+}
+
+impl<R: ModularRing> EdwardsPoint<R> {
+    /// (Z + Y : Z - Y) on the Montgomery curve of `EdwardsCurve::montgomery_a24`
+    pub fn to_montgomery<'c>(&self, curve: &'c MontgomeryCurve<'c, R>) -> MontgomeryPoint<'c, R> {
+        let ring = curve.ring();
+        curve.point_from_residues(ring.add(&self.z, &self.y), ring.sub(&self.z, &self.y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::integer::Complete;
+    use crate::arith::modular_arithmetic::*;
+    use crate::arith::ring::DefaultRing;
+
+    /// f of the Montgomery curve A = 10 mod 1000003, and of its Edwards
+    /// form of coefficient a with a point of it
+    fn with_curve<T>(
+        twist: Twist,
+        f: impl for<'c> FnOnce(
+            &'c MontgomeryCurve<'c, DefaultRing>,
+            EdwardsCurve<DefaultRing>,
+            EdwardsPoint<DefaultRing>,
+        ) -> T,
+    ) -> T {
+        let p = Integer::from(1000003);
+        let a = Integer::from(10);
+        MontgomeryCurve::<DefaultRing>::with_a(a.clone(), p.clone(), |montgomery| {
+            // a(A + 2) v^2 = u^3 + Au^2 + u, square roots being powers as
+            // p = 3 mod 4
+            let b = match twist {
+                Twist::MinusOne => (&p - 12u32).complete(),
+                Twist::One => Integer::from(12),
+            };
+            for u in 2.. {
+                let u = Integer::from(u);
+                let f2 = (&u * &u).complete() * &u + (&a * &u).complete() * &u + &u;
+                let v2 = multiply_mod(&f2, &invert_mod(&b, &p).unwrap(), &p);
+                let v = v2
+                    .clone()
+                    .pow_mod(&((&p + 1u32).complete() >> 2), &p)
+                    .unwrap();
+                if multiply_mod(&v, &v, &p) == v2 && v != 0 {
+                    let (edwards, q) =
+                        EdwardsCurve::from_montgomery(twist, montgomery, &u, &v).unwrap();
+                    return f(montgomery, edwards, q);
+                }
+            }
+            unreachable!()
+        })
+    }
+
+    #[test]
+    fn edwards_group_tests() {
+        for twist in [Twist::MinusOne, Twist::One] {
+            with_curve(twist, |_, curve, p| {
+                assert!(curve.is_on_curve(&p));
+                assert!(curve.is_on_curve(&curve.neutral()));
+                let equal = |a: &EdwardsPoint<DefaultRing>, b: &EdwardsPoint<DefaultRing>| {
+                    let ring = curve.ring();
+                    ring.mul(&a.x, &b.z) == ring.mul(&b.x, &a.z)
+                        && ring.mul(&a.y, &b.z) == ring.mul(&b.y, &a.z)
+                };
+
+                let mut p2 = curve.neutral();
+                curve.double_into(&mut p2, &p);
+                let mut sum = curve.neutral();
+                curve.add_into(&mut sum, &p, &p);
+                assert!(curve.is_on_curve(&p2));
+                assert!(equal(&p2, &sum));
+
+                let mut p3 = curve.neutral();
+                curve.triple_into(&mut p3, &p);
+                curve.add_into(&mut sum, &p2, &p);
+                assert!(curve.is_on_curve(&p3));
+                assert!(equal(&p3, &sum));
+
+                // mixed addition with p, which has Z = 1 after normalizing
+                let ring = curve.ring();
+                let z = ring.invert(&p.z).unwrap();
+                let normalized = EdwardsPoint {
+                    x: ring.mul(&p.x, &z),
+                    y: ring.mul(&p.y, &z),
+                    z: ring.one(),
+                    t: ring.mul(&p.t, &z),
+                };
+                let mut mixed = curve.neutral();
+                curve.add_mixed_into(&mut mixed, &p2, &normalized);
+                assert!(equal(&mixed, &p3));
+
+                // p + -p = 0
+                curve.add_into(&mut sum, &p, &curve.neg(&p));
+                assert!(equal(&sum, &curve.neutral()));
+            });
+        }
+    }
+
+    #[test]
+    fn edwards_mul_tests() {
+        for twist in [Twist::MinusOne, Twist::One] {
+            with_curve(twist, |montgomery, curve, p| {
+                assert_eq!(&curve.montgomery_a24().unwrap(), montgomery.a24());
+                let xz = p.to_montgomery(montgomery);
+                for k in [1u64, 2, 3, 7, 12, 1000, 123456789, u64::MAX] {
+                    let k = Integer::from(k);
+                    let q = curve.mul(&p, &k);
+                    assert!(curve.is_on_curve(&q), "k={}", k);
+                    assert!(
+                        q.to_montgomery(montgomery)
+                            .equals(&montgomery.montgomery_ladder(&xz, &k)),
+                        "k={}",
+                        k
+                    );
+                }
+                // large enough for the widest windows
+                let k = Integer::u_pow_u(3, 5000).complete() + 1;
+                let q = curve.mul(&p, &k);
+                assert!(q
+                    .to_montgomery(montgomery)
+                    .equals(&montgomery.montgomery_ladder(&xz, &k)));
+            });
+        }
+    }
+
+    /// Edwards to Montgomery and back gives the same curve and point
+    #[test]
+    fn edwards_montgomery_tests() {
+        for twist in [Twist::MinusOne, Twist::One] {
+            with_curve(twist, |montgomery, curve, p| {
+                let ring = curve.ring();
+                let (full, q) = curve.to_full_montgomery(&p).unwrap();
+                assert_eq!(&full.a24(), montgomery.a24());
+                assert!(full.is_on_curve(&q));
+                let z = ring.invert(&q.z).unwrap();
+                let (u, v) = (ring.mul(&q.x, &z), ring.mul(&q.y, &z));
+                let (u, v) = (ring.to_integer(&u), ring.to_integer(&v));
+                let (back, r) = EdwardsCurve::from_montgomery(twist, montgomery, &u, &v).unwrap();
+                assert_eq!(back.d(), curve.d());
+                assert_eq!(ring.mul(&r.x, &p.z), ring.mul(&p.x, &r.z));
+                assert_eq!(ring.mul(&r.y, &p.z), ring.mul(&p.y, &r.z));
+            });
+        }
+    }
+
+    #[test]
+    fn naf_tests() {
+        for w in 2..7 {
+            for k in [1u64, 2, 5, 255, 1000003, 0xdeadbeef] {
+                let digits = naf(&Integer::from(k), w);
+                let value = digits
+                    .iter()
+                    .rev()
+                    .fold(Integer::new(), |acc, &d| 2 * acc + d);
+                assert_eq!(value, k);
+                assert!(digits
+                    .iter()
+                    .all(|&d| d == 0 || (d % 2 != 0 && d.abs() < 1 << (w - 1))));
+                // no two nonzero digits within w positions of each other
+                for i in (0..digits.len()).filter(|&i| digits[i] != 0) {
+                    assert!(digits[i + 1..].iter().take(w as usize - 1).all(|&e| e == 0));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use crate::arith::integer::{Integer, Order};
+use crate::arith::modular_arithmetic::take_mod;
+use crate::arith::ring::ModularRing;
+use std::cmp::Ordering;
+
+/// r = a + b, returns the carry
+#[inline(always)]
+pub(crate) fn add_n<const N: usize>(r: &mut [u64; N], a: &[u64; N], b: &[u64; N]) -> bool {
+    let mut carry = false;
+    for i in 0..N {
+        let (s, c1) = a[i].overflowing_add(b[i]);
+        let (s, c2) = s.overflowing_add(carry as u64);
+        r[i] = s;
+        carry = c1 | c2;
+    }
+    carry
+}
+
+/// r = a - b, returns the borrow
+#[inline(always)]
+pub(crate) fn sub_n<const N: usize>(r: &mut [u64; N], a: &[u64; N], b: &[u64; N]) -> bool {
+    let mut borrow = false;
+    for i in 0..N {
+        let (s, b1) = a[i].overflowing_sub(b[i]);
+        let (s, b2) = s.overflowing_sub(borrow as u64);
+        r[i] = s;
+        borrow = b1 | b2;
+    }
+    borrow
+}
+
+#[inline(always)]
+pub(crate) fn cmp_n<const N: usize>(a: &[u64; N], b: &[u64; N]) -> Ordering {
+    for i in (0..N).rev() {
+        match a[i].cmp(&b[i]) {
+            Ordering::Equal => (),
+            o => return o,
+        }
+    }
+    Ordering::Equal
+}
+
+/// a + b * c + carry, as (low, high) limbs
+#[inline(always)]
+fn mac(a: u64, b: u64, c: u64, carry: u64) -> (u64, u64) {
+    let t = a as u128 + (b as u128) * (c as u128) + carry as u128;
+    (t as u64, (t >> 64) as u64)
+}
+
+/// Montgomery product: writes a.b.R^{-1} mod n to r, R = 2^(64*N),
+/// for a, b < n and n_inv = -n^{-1} mod 2^64
+#[inline(always)]
+pub(crate) fn mont_mul<const N: usize>(
+    r: &mut [u64; N],
+    a: &[u64; N],
+    b: &[u64; N],
+    n: &[u64; N],
+    n_inv: u64,
+) {
+    // t holds N + 2 limbs: t[..N], t_hi and t_top
+    let mut t = [0u64; N];
+    let mut t_hi = 0u64;
+    for &b_i in b.iter() {
+        // t += a * b_i
+        let mut carry = 0;
+        for j in 0..N {
+            (t[j], carry) = mac(t[j], a[j], b_i, carry);
+        }
+        let (s, c) = t_hi.overflowing_add(carry);
+        t_hi = s;
+        let t_top = c as u64;
+
+        // t = (t + m * n) / 2^64, m chosen s.t. the low limb vanishes
+        let m = t[0].wrapping_mul(n_inv);
+        let (_, mut carry) = mac(t[0], m, n[0], 0);
+        for j in 1..N {
+            (t[j - 1], carry) = mac(t[j], m, n[j], carry);
+        }
+        let (s, c) = t_hi.overflowing_add(carry);
+        t[N - 1] = s;
+        t_hi = t_top + c as u64;
+    }
+    // t < 2n here
+    if t_hi != 0 || cmp_n(&t, n) != Ordering::Less {
+        sub_n(r, &t, n);
+    } else {
+        *r = t;
+    }
+}
+
+/// -n_0^{-1} mod 2^64 for an odd n_0
+pub(crate) fn neg_inverse(n_0: u64) -> u64 {
+    // Newton's iteration doubles the number of correct low bits of
+    // n_0^{-1} each time, starting from 1 correct bit
+    let mut inv: u64 = 1;
+    for _ in 0..6 {
+        inv = inv.wrapping_mul(2u64.wrapping_sub(n_0.wrapping_mul(inv)));
+    }
+    inv.wrapping_neg()
+}
+
+/// Arithmetic mod an odd n < 2^(64*N) in Montgomery representation, with
+/// residues stored inline as N limbs least significant first.
+///
+/// Multiplication interleaves the product and the reduction one limb at a
+/// time (Koç et al.'s CIOS method), so that for the sizes ECM is usually
+/// run on, the loops are fully unrolled and nothing goes through the heap.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FixedRing<const N: usize> {
+    n: Integer,
+    n_limbs: [u64; N],
+    /// -n^{-1} mod 2^64
+    n_inv: u64,
+    /// R^2 mod n, R = 2^(64*N)
+    r2: [u64; N],
+    one: [u64; N],
+}
+
+impl<const N: usize> ModularRing for FixedRing<N> {
+    type Elem = [u64; N];
+
+    /// Returns None if n is even, smaller than 3 or does not fit in N limbs
+    fn new(n: &Integer) -> Option<FixedRing<N>> {
+        if n.is_even() || *n < 3 || n.significant_bits() as usize > 64 * N {
+            return None;
+        }
+        let mut n_limbs = [0u64; N];
+        n.write_digits(&mut n_limbs, Order::Lsf);
+
+        let mut ring = FixedRing {
+            n: Integer::from(n),
+            n_limbs,
+            n_inv: neg_inverse(n_limbs[0]),
+            r2: [0; N],
+            one: [0; N],
+        };
+        let r2 = take_mod(&(Integer::from(1) << (128 * N as u32)), n);
+        r2.write_digits(&mut ring.r2, Order::Lsf);
+        ring.one = ring.to_residue(&Integer::from(1));
+        Some(ring)
+    }
+
+    fn modulus(&self) -> &Integer {
+        &self.n
+    }
+
+    /// Converts a into Montgomery representation
+    fn to_residue(&self, a: &Integer) -> [u64; N] {
+        let mut r = [0; N];
+        take_mod(a, &self.n).write_digits(&mut r, Order::Lsf);
+        self.mul_assign(&mut r, &self.r2);
+        r
+    }
+
+    /// Converts a back from Montgomery representation
+    fn to_integer(&self, a: &[u64; N]) -> Integer {
+        let mut one = [0; N];
+        one[0] = 1;
+        let mut r = [0; N];
+        mont_mul(&mut r, a, &one, &self.n_limbs, self.n_inv);
+        Integer::from_digits(&r, Order::Lsf)
+    }
+
+    fn zero(&self) -> [u64; N] {
+        [0; N]
+    }
+
+    fn one(&self) -> [u64; N] {
+        self.one
+    }
+
+    fn is_zero(&self, a: &[u64; N]) -> bool {
+        a.iter().all(|&l| l == 0)
+    }
+
+    fn add_into(&self, r: &mut [u64; N], a: &[u64; N], b: &[u64; N]) {
+        let carry = add_n(r, a, b);
+        if carry || cmp_n(r, &self.n_limbs) != Ordering::Less {
+            let s = *r;
+            sub_n(r, &s, &self.n_limbs);
+        }
+    }
+
+    fn add_assign(&self, r: &mut [u64; N], b: &[u64; N]) {
+        let a = *r;
+        self.add_into(r, &a, b);
+    }
+
+    fn sub_into(&self, r: &mut [u64; N], a: &[u64; N], b: &[u64; N]) {
+        if sub_n(r, a, b) {
+            let s = *r;
+            add_n(r, &s, &self.n_limbs);
+        }
+    }
+
+    fn sub_assign(&self, r: &mut [u64; N], b: &[u64; N]) {
+        let a = *r;
+        self.sub_into(r, &a, b);
+    }
+
+    fn mul_into(&self, r: &mut [u64; N], a: &[u64; N], b: &[u64; N]) {
+        mont_mul(r, a, b, &self.n_limbs, self.n_inv);
+    }
+
+    fn mul_assign(&self, r: &mut [u64; N], b: &[u64; N]) {
+        let a = *r;
+        mont_mul(r, &a, b, &self.n_limbs, self.n_inv);
+    }
+
+    fn square_into(&self, r: &mut [u64; N], a: &[u64; N]) {
+        mont_mul(r, a, a, &self.n_limbs, self.n_inv);
+    }
+
+    fn square_assign(&self, r: &mut [u64; N]) {
+        let a = *r;
+        mont_mul(r, &a, &a, &self.n_limbs, self.n_inv);
+    }
+
+    /// gcd(a, n), computed directly on the Montgomery representation
+    /// since R is coprime to n
+    fn gcd(&self, a: &[u64; N]) -> Integer {
+        Integer::from_digits(a, Order::Lsf).gcd(&self.n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::integer::{Complete, RandState};
+    use crate::arith::montgomery_point::MontgomeryCurve;
+    use crate::arith::ring::IntegerRing;
+
+    /// Random odd modulus of exactly the given number of bits
+    fn random_modulus(rand: &mut RandState, bits: u32) -> Integer {
+        let n = Integer::random_bits(bits - 1, rand).complete() | (Integer::from(1) << (bits - 1));
+        n | 1
+    }
+
+    /// Compares every ring operation with `IntegerRing`, for moduli of
+    /// each bit size that uses exactly N limbs
+    fn cross_check<const N: usize>(rand: &mut RandState) {
+        for bits in [64 * N as u32 - 63, 64 * N as u32 - 1, 64 * N as u32] {
+            let bits = bits.max(2);
+            let n = random_modulus(rand, bits);
+            let fixed = FixedRing::<N>::new(&n).unwrap();
+            let reference = IntegerRing::new(&n).unwrap();
+            for _ in 0..20 {
+                let a = n.random_below_ref(rand).complete();
+                let b = n.random_below_ref(rand).complete();
+                let (fa, fb) = (fixed.to_residue(&a), fixed.to_residue(&b));
+                let (ra, rb) = (reference.to_residue(&a), reference.to_residue(&b));
+                assert_eq!(fixed.to_integer(&fa), a);
+                assert_eq!(
+                    fixed.to_integer(&fixed.add(&fa, &fb)),
+                    reference.add(&ra, &rb)
+                );
+                assert_eq!(
+                    fixed.to_integer(&fixed.sub(&fa, &fb)),
+                    reference.sub(&ra, &rb)
+                );
+                assert_eq!(
+                    fixed.to_integer(&fixed.mul(&fa, &fb)),
+                    reference.mul(&ra, &rb)
+                );
+                assert_eq!(fixed.to_integer(&fixed.square(&fa)), reference.square(&ra));
+            }
+            // extreme operands: n - 1 squared and added to itself
+            let m = (&n - 1u32).complete();
+            let fm = fixed.to_residue(&m);
+            let rm = reference.to_residue(&m);
+            assert_eq!(fixed.to_integer(&fixed.square(&fm)), 1);
+            assert_eq!(
+                fixed.to_integer(&fixed.add(&fm, &fm)),
+                reference.add(&rm, &rm)
+            );
+
+            // the curve arithmetic agrees as well
+            let a24 = n.random_below_ref(rand).complete();
+            let x = n.random_below_ref(rand).complete();
+            let k = Integer::random_bits(200, rand).complete();
+            let p = MontgomeryCurve::with(fixed.to_residue(&a24), fixed.clone(), |curve| {
+                let p = curve.montgomery_ladder(&curve.point(&x, &Integer::from(1)), &k);
+                (p.x, p.z)
+            });
+            let q = MontgomeryCurve::with(a24.clone(), reference.clone(), |curve| {
+                let q = curve.montgomery_ladder(&curve.point(&x, &Integer::from(1)), &k);
+                (q.x, q.z)
+            });
+            assert_eq!(
+                fixed.mul(&p.0, &fixed.to_residue(&q.1)),
+                fixed.mul(&p.1, &fixed.to_residue(&q.0))
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_ring_cross_check() {
+        let mut rand = RandState::new();
+        cross_check::<1>(&mut rand);
+        cross_check::<2>(&mut rand);
+        cross_check::<3>(&mut rand);
+        cross_check::<4>(&mut rand);
+        cross_check::<7>(&mut rand);
+        cross_check::<8>(&mut rand);
+        cross_check::<13>(&mut rand);
+        cross_check::<16>(&mut rand);
+    }
+
+    #[test]
+    fn fixed_ring_new() {
+        assert!(FixedRing::<1>::new(&Integer::from(100)).is_none());
+        assert!(FixedRing::<1>::new(&Integer::from(1)).is_none());
+        // 2^64 + 1 does not fit in one limb
+        assert!(FixedRing::<1>::new(&((Integer::from(1) << 64) + 1)).is_none());
+        assert!(FixedRing::<2>::new(&((Integer::from(1) << 64) + 1)).is_some());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::arith::integer::{Integer, Order};
+use crate::arith::modular_arithmetic::*;
+use crate::arith::ring::ModularRing;
+use gmp_mpfr_sys::gmp;
+use gmp_mpfr_sys::gmp::limb_t;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+
+// Safe wrappers around GMP's low-level mpn functions. Operands are
+// slices of equal length (the number of limbs of the modulus) and the
+// destination never overlaps the sources, except for the *_assign
+// variants which GMP supports in place.
+
+fn mpn_mul_n(r: &mut [limb_t], a: &[limb_t], b: &[limb_t]) {
+    debug_assert!(r.len() >= 2 * a.len() && a.len() == b.len());
+    unsafe {
+        gmp::mpn_mul_n(
+            r.as_mut_ptr(),
+            a.as_ptr(),
+            b.as_ptr(),
+            a.len() as gmp::size_t,
+        )
+    }
+}
+
+fn mpn_sqr(r: &mut [limb_t], a: &[limb_t]) {
+    debug_assert!(r.len() >= 2 * a.len());
+    unsafe { gmp::mpn_sqr(r.as_mut_ptr(), a.as_ptr(), a.len() as gmp::size_t) }
+}
+
+fn mpn_add_n(r: &mut [limb_t], a: &[limb_t], b: &[limb_t]) -> limb_t {
+    debug_assert!(r.len() == a.len() && a.len() == b.len());
+    unsafe {
+        gmp::mpn_add_n(
+            r.as_mut_ptr(),
+            a.as_ptr(),
+            b.as_ptr(),
+            a.len() as gmp::size_t,
+        )
+    }
+}
+
+fn mpn_sub_n(r: &mut [limb_t], a: &[limb_t], b: &[limb_t]) -> limb_t {
+    debug_assert!(r.len() == a.len() && a.len() == b.len());
+    unsafe {
+        gmp::mpn_sub_n(
+            r.as_mut_ptr(),
+            a.as_ptr(),
+            b.as_ptr(),
+            a.len() as gmp::size_t,
+        )
+    }
+}
+
+/// r += a, returns the carry limb
+fn mpn_add_n_assign(r: &mut [limb_t], a: &[limb_t]) -> limb_t {
+    debug_assert!(r.len() == a.len());
+    let rp = r.as_mut_ptr();
+    unsafe { gmp::mpn_add_n(rp, rp, a.as_ptr(), a.len() as gmp::size_t) }
+}
+
+/// r -= a, returns the borrow limb
+fn mpn_sub_n_assign(r: &mut [limb_t], a: &[limb_t]) -> limb_t {
+    debug_assert!(r.len() == a.len());
+    let rp = r.as_mut_ptr();
+    unsafe { gmp::mpn_sub_n(rp, rp, a.as_ptr(), a.len() as gmp::size_t) }
+}
+
+/// r += a * b, returns the carry limb
+fn mpn_addmul_1(r: &mut [limb_t], a: &[limb_t], b: limb_t) -> limb_t {
+    debug_assert!(r.len() >= a.len());
+    unsafe { gmp::mpn_addmul_1(r.as_mut_ptr(), a.as_ptr(), a.len() as gmp::size_t, b) }
+}
+
+fn mpn_cmp(a: &[limb_t], b: &[limb_t]) -> Ordering {
+    debug_assert!(a.len() == b.len());
+    unsafe { gmp::mpn_cmp(a.as_ptr(), b.as_ptr(), a.len() as gmp::size_t) }.cmp(&0)
+}
+
+/// A residue mod n kept in Montgomery representation, i.e. the value a
+/// is stored as aR mod n, R = 2^(64*k), as k limbs least significant first.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Residue(Vec<limb_t>);
+
+thread_local! {
+    /// Per-thread buffer holding the double-length products reduced by REDC
+    static PRODUCT: RefCell<Vec<limb_t>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Arithmetic mod an odd n in Montgomery representation, on top of
+/// GMP's mpn layer. Products are reduced with Montgomery's REDC, one limb
+/// at a time, instead of a division by n.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MontgomeryContext {
+    n: Integer,
+    n_limbs: Vec<limb_t>,
+    /// -n^{-1} mod 2^64
+    n_inv: limb_t,
+    /// R^2 mod n, used to enter Montgomery representation
+    r2: Residue,
+    one: Residue,
+}
+
+impl MontgomeryContext {
+    fn limbs(&self) -> usize {
+        self.n_limbs.len()
+    }
+
+    /// Montgomery's REDC: writes t.R^{-1} mod n to r for t < nR of 2k limbs,
+    /// t is clobbered.
+    fn redc(&self, r: &mut [limb_t], t: &mut [limb_t]) {
+        let k = self.limbs();
+        for i in 0..k {
+            let q = t[i].wrapping_mul(self.n_inv);
+            // t[i] is now zero mod 2^64, keep the carry there and
+            // add all of them to the high half at the end
+            t[i] = mpn_addmul_1(&mut t[i..i + k], &self.n_limbs, q);
+        }
+        let (carries, high) = t.split_at(k);
+        let carry = mpn_add_n(r, high, carries);
+        if carry != 0 || mpn_cmp(r, &self.n_limbs) != Ordering::Less {
+            mpn_sub_n_assign(r, &self.n_limbs);
+        }
+    }
+
+    /// Runs f on the per-thread product buffer, sized to 2k limbs
+    fn with_product<F: FnOnce(&mut [limb_t])>(&self, f: F) {
+        PRODUCT.with(|t| {
+            let mut t = t.borrow_mut();
+            t.resize(2 * self.limbs(), 0);
+            f(&mut t)
+        })
+    }
+}
+
+impl ModularRing for MontgomeryContext {
+    type Elem = Residue;
+
+    /// Returns None if n is even (or smaller than 3), in which case
+    /// R is not invertible mod n.
+    fn new(n: &Integer) -> Option<MontgomeryContext> {
+        if n.is_even() || *n < 3 {
+            return None;
+        }
+        let limbs = n.significant_bits().div_ceil(limb_t::BITS) as usize;
+        let mut n_limbs: Vec<limb_t> = vec![0; limbs];
+        n.write_digits(&mut n_limbs, Order::Lsf);
+
+        // Newton's iteration doubles the number of correct low bits of
+        // n_0^{-1} each time, starting from 1 correct bit
+        let mut inv: limb_t = 1;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul((2 as limb_t).wrapping_sub(n_limbs[0].wrapping_mul(inv)));
+        }
+
+        let r2 = take_mod(&(Integer::from(1) << (2 * limbs as u32 * limb_t::BITS)), n);
+        let mut ctx = MontgomeryContext {
+            n: Integer::from(n),
+            n_limbs,
+            n_inv: inv.wrapping_neg(),
+            r2: Residue(vec![0; limbs]),
+            one: Residue::default(),
+        };
+        r2.write_digits(&mut ctx.r2.0, Order::Lsf);
+        ctx.one = ctx.to_residue(&Integer::from(1));
+        Some(ctx)
+    }
+
+    fn modulus(&self) -> &Integer {
+        &self.n
+    }
+
+    /// Converts a into Montgomery representation
+    fn to_residue(&self, a: &Integer) -> Residue {
+        let mut a_limbs = Residue(vec![0; self.limbs()]);
+        take_mod(a, &self.n).write_digits(&mut a_limbs.0, Order::Lsf);
+        self.mul_assign(&mut a_limbs, &self.r2);
+        a_limbs
+    }
+
+    /// Converts a back from Montgomery representation
+    fn to_integer(&self, a: &Residue) -> Integer {
+        let mut r = vec![0; self.limbs()];
+        self.with_product(|t| {
+            t[..self.limbs()].copy_from_slice(&a.0);
+            t[self.limbs()..].fill(0);
+            self.redc(&mut r, t);
+        });
+        Integer::from_digits(&r, Order::Lsf)
+    }
+
+    fn zero(&self) -> Residue {
+        Residue(vec![0; self.limbs()])
+    }
+
+    fn one(&self) -> Residue {
+        self.one.clone()
+    }
+
+    fn is_zero(&self, a: &Residue) -> bool {
+        a.0.iter().all(|&l| l == 0)
+    }
+
+    fn add_into(&self, r: &mut Residue, a: &Residue, b: &Residue) {
+        let carry = mpn_add_n(&mut r.0, &a.0, &b.0);
+        if carry != 0 || mpn_cmp(&r.0, &self.n_limbs) != Ordering::Less {
+            mpn_sub_n_assign(&mut r.0, &self.n_limbs);
+        }
+    }
+
+    fn add_assign(&self, r: &mut Residue, b: &Residue) {
+        let carry = mpn_add_n_assign(&mut r.0, &b.0);
+        if carry != 0 || mpn_cmp(&r.0, &self.n_limbs) != Ordering::Less {
+            mpn_sub_n_assign(&mut r.0, &self.n_limbs);
+        }
+    }
+
+    fn sub_into(&self, r: &mut Residue, a: &Residue, b: &Residue) {
+        if mpn_sub_n(&mut r.0, &a.0, &b.0) != 0 {
+            mpn_add_n_assign(&mut r.0, &self.n_limbs);
+        }
+    }
+
+    fn sub_assign(&self, r: &mut Residue, b: &Residue) {
+        if mpn_sub_n_assign(&mut r.0, &b.0) != 0 {
+            mpn_add_n_assign(&mut r.0, &self.n_limbs);
+        }
+    }
+
+    fn mul_into(&self, r: &mut Residue, a: &Residue, b: &Residue) {
+        self.with_product(|t| {
+            mpn_mul_n(t, &a.0, &b.0);
+            self.redc(&mut r.0, t);
+        })
+    }
+
+    fn mul_assign(&self, r: &mut Residue, b: &Residue) {
+        self.with_product(|t| {
+            mpn_mul_n(t, &r.0, &b.0);
+            self.redc(&mut r.0, t);
+        })
+    }
+
+    fn square_into(&self, r: &mut Residue, a: &Residue) {
+        self.with_product(|t| {
+            mpn_sqr(t, &a.0);
+            self.redc(&mut r.0, t);
+        })
+    }
+
+    fn square_assign(&self, r: &mut Residue) {
+        self.with_product(|t| {
+            mpn_sqr(t, &r.0);
+            self.redc(&mut r.0, t);
+        })
+    }
+
+    /// gcd(a, n), computed directly on the Montgomery representation
+    /// since R is coprime to n
+    fn gcd(&self, a: &Residue) -> Integer {
+        Integer::from_digits(&a.0, Order::Lsf).gcd(&self.n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn montgomery_context_tests() {
+        assert!(MontgomeryContext::new(&Integer::from(100)).is_none());
+        // R = 2^64 for a one-limb modulus
+        let ctx = MontgomeryContext::new(&Integer::from(1000003)).unwrap();
+        let r = Integer::from(1) << 64;
+        assert_eq!(
+            ctx.one,
+            Residue(vec![take_mod(&r, &Integer::from(1000003))
+                .to_u64()
+                .unwrap()])
+        );
+    }
+}
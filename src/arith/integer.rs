@@ -0,0 +1,32 @@
+//! The integers of the crate and of its interface: rug's `Integer` with
+//! the `gmp` feature, and otherwise the pure Rust `bignum::Integer`, which
+//! has the same interface for the part of rug the crate uses. The rest of
+//! the crate takes its integers, random states and their traits from here.
+#[cfg(not(feature = "gmp"))]
+pub use crate::arith::bignum::{Assign, Integer, IsPrime, Order, Pow, RandState};
+#[cfg(feature = "gmp")]
+pub use rug::{
+    integer::{IsPrime, Order},
+    ops::Pow,
+    rand::RandState,
+    Assign, Integer,
+};
+
+/// Turns what an operation on borrowed integers returns, `&a * &b` or
+/// `a.gcd_ref(&b)`, into an integer. rug returns incomplete computation
+/// values, to be completed like `Integer::from` does; the pure Rust
+/// operations return integers already, which are kept as they are.
+pub trait Complete {
+    fn complete(self) -> Integer;
+}
+
+impl<T> Complete for T
+where
+    Integer: Assign<T>,
+{
+    fn complete(self) -> Integer {
+        let mut integer = Integer::new();
+        integer.assign(self);
+        integer
+    }
+}
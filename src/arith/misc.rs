@@ -1,22 +1,203 @@
+use crate::arith::integer::{Assign, Integer, RandState};
 use crate::arith::modular_arithmetic::*;
-use rug::{rand::RandState, Assign, Integer};
-
-// TODO: optimize
-pub fn eratosthenes(primes: &mut Vec<bool>, limit: usize) {
-    primes[0] = false;
-    primes[1] = false;
-    primes[2] = true;
-
-    let slimit = f64::sqrt(limit as f64) as usize;
-    for i in (3..slimit).step_by(2) {
-        if primes[i / 2] {
-            for j in ((i * i)..limit).step_by(2 * i) {
-                primes[j / 2] = false;
+use std::ops::Range;
+
+/// The primes of a range, one bit per odd number.
+///
+/// Built from `Primes`, this is the random access counterpart of the
+/// stream: whether a number of the range is prime, and the primes of
+/// any subrange.
+pub struct PrimeTable {
+    range: Range<u64>,
+    /// First odd number >= range.start, bit i of the table stands for
+    /// odd + 2i
+    odd: u64,
+    bits: Vec<u64>,
+}
+
+impl PrimeTable {
+    pub fn new(range: Range<u64>) -> PrimeTable {
+        let odd = range.start | 1;
+        let len = range.end.saturating_sub(odd).div_ceil(2);
+        let mut bits = vec![0u64; len.div_ceil(64) as usize];
+        for p in Primes::new(odd..range.end).filter(|&p| p != 2) {
+            let i = (p - odd) / 2;
+            bits[(i / 64) as usize] |= 1 << (i % 64);
+        }
+        PrimeTable { range, odd, bits }
+    }
+
+    pub fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+
+    /// Panics if n is not in the range of the table
+    pub fn is_prime(&self, n: u64) -> bool {
+        assert!(
+            self.range.contains(&n),
+            "{} is not in the prime table's range {:?}",
+            n,
+            self.range
+        );
+        if n.is_multiple_of(2) {
+            return n == 2;
+        }
+        let i = (n - self.odd) / 2;
+        self.bits[(i / 64) as usize] >> (i % 64) & 1 == 1
+    }
+
+    /// The primes of range in increasing order.
+    /// Panics if range is not within the range of the table.
+    pub fn primes_in(&self, range: Range<u64>) -> impl Iterator<Item = u64> + '_ {
+        assert!(
+            range.is_empty() || (self.range.start <= range.start && range.end <= self.range.end),
+            "{:?} is not within the prime table's range {:?}",
+            range,
+            self.range
+        );
+        let two = (range.start <= 2 && 2 < range.end).then_some(2);
+        two.into_iter().chain(
+            (range.start.max(3) | 1..range.end)
+                .step_by(2)
+                .filter(move |&n| self.is_prime(n)),
+        )
+    }
+}
+
+/// Number of odd integers sieved at once, i.e. 32 KiB worth of bits
+const SEGMENT_BITS: u64 = 1 << 18;
+
+/// Streaming iterator over the primes of a range, in increasing order.
+///
+/// Only odd numbers are sieved, one bit each, one segment of
+/// `SEGMENT_BITS` odd numbers at a time. The primes up to the square
+/// root of the current segment are themselves pulled from a nested
+/// `Primes`, so memory stays bounded by the segment and those primes
+/// whatever the range.
+pub struct Primes {
+    end: u64,
+    /// Whether 2 is yet to be yielded
+    two: bool,
+    /// Source of the odd primes sieving the segments
+    base: Option<Box<Primes>>,
+    /// Next base prime not in use yet
+    next_base: Option<u64>,
+    /// Base primes in use, with their next odd multiple to cross out
+    sieving: Vec<(u64, u64)>,
+    /// The current segment holds the odd numbers of [lo, hi)
+    lo: u64,
+    hi: u64,
+    words: Vec<u64>,
+    /// Bits of words[word] not yielded yet
+    word: usize,
+    current: u64,
+}
+
+impl Primes {
+    pub fn new(range: Range<u64>) -> Primes {
+        let lo = match range.start.max(3) {
+            s if s.is_multiple_of(2) => s + 1,
+            s => s,
+        };
+        let base_end = isqrt(range.end.saturating_sub(1)) + 1;
+        let mut primes = Primes {
+            end: range.end,
+            two: range.start <= 2 && 2 < range.end,
+            base: if base_end > 3 {
+                Some(Box::new(Primes::new(3..base_end)))
+            } else {
+                None
+            },
+            next_base: None,
+            sieving: vec![],
+            lo,
+            hi: lo,
+            words: vec![],
+            word: 0,
+            current: 0,
+        };
+        primes.next_base = primes.base.as_mut().and_then(|b| b.next());
+        primes
+    }
+
+    /// Sieves the odd numbers of [lo, hi), lo odd
+    fn sieve_segment(&mut self) {
+        let (lo, hi) = (self.lo, self.hi);
+        let bits = (hi - lo).div_ceil(2);
+        self.words.clear();
+        self.words.resize(bits.div_ceil(64) as usize, u64::MAX);
+        if bits % 64 != 0 {
+            *self.words.last_mut().unwrap() = (1 << (bits % 64)) - 1;
+        }
+
+        // Bring in the base primes whose square is in the segment
+        while let Some(p) = self.next_base {
+            if p.saturating_mul(p) >= hi {
+                break;
             }
+            self.sieving.push((p, p * p));
+            self.next_base = self.base.as_mut().and_then(|b| b.next());
         }
+
+        for (p, next) in self.sieving.iter_mut() {
+            if *next < lo {
+                // first odd multiple of p >= lo
+                let m = lo.div_ceil(*p) * *p;
+                *next = if m.is_multiple_of(2) { m + *p } else { m };
+            }
+            while *next < hi {
+                let i = (*next - lo) / 2;
+                self.words[(i / 64) as usize] &= !(1 << (i % 64));
+                *next = next.saturating_add(2 * *p);
+            }
+        }
+        self.word = 0;
+        self.current = self.words.first().copied().unwrap_or(0);
     }
 }
 
+impl Iterator for Primes {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.two {
+            self.two = false;
+            return Some(2);
+        }
+        loop {
+            if self.current != 0 {
+                let bit = self.current.trailing_zeros() as u64;
+                self.current &= self.current - 1;
+                return Some(self.lo + 2 * (64 * self.word as u64 + bit));
+            }
+            if self.word + 1 < self.words.len() {
+                self.word += 1;
+                self.current = self.words[self.word];
+                continue;
+            }
+            // Move on to the next segment
+            if self.hi >= self.end {
+                return None;
+            }
+            self.lo = self.hi;
+            self.hi = self.end.min(self.lo.saturating_add(2 * SEGMENT_BITS));
+            self.sieve_segment();
+        }
+    }
+}
+
+/// Largest r such that r^2 <= n
+pub fn isqrt(n: u64) -> u64 {
+    let mut r = (n as f64).sqrt() as u64;
+    while r.checked_mul(r).is_none_or(|s| s > n) {
+        r -= 1;
+    }
+    while (r + 1).checked_mul(r + 1).is_some_and(|s| s <= n) {
+        r += 1;
+    }
+    r
+}
+
 pub fn bits_amount(n: &Integer) -> u32 {
     let mut amount = 0;
     let mut m = n.clone();
@@ -42,10 +223,9 @@ pub fn bits(n: &Integer) -> Vec<char> {
     format!("{:b}", n).chars().collect()
 }
 
+/// A uniformly random integer of [min, max], min <= max
 pub fn randint(rand: &mut RandState, min: &Integer, max: &Integer) -> Integer {
-    let min_bits = bits_amount(min);
-    let max_bits = bits_amount(max);
-    Integer::from(Integer::random_bits(max_bits - min_bits + 1, rand)) + min_bits
+    (max.clone() - min + 1u32).random_below(rand) + min
 }
 
 /// Returns (e, bool) where e is the largest integer such that
@@ -67,7 +247,7 @@ pub fn integer_log(y: u64, x: u64) -> Option<(u32, bool)> {
                 while yy >= d {
                     let div = div_mod(&Integer::from(yy), &Integer::from(d));
                     yy = div.0.to_u64().unwrap();
-                    r = r | div.1.to_u64().unwrap();
+                    r |= div.1.to_u64().unwrap();
                     e += m;
                     if yy > d {
                         d *= d;
@@ -94,13 +274,13 @@ pub fn fast_pow(a: &Integer, n: &Integer) -> Integer {
                 a_copy.square_mut();
                 n_copy >>= 1;
             } else {
-                acc = acc * &a_copy;
+                acc *= &a_copy;
                 a_copy.square_mut();
                 n_copy -= 1;
                 n_copy >>= 1;
             }
         }
-        acc = acc * &a_copy;
+        acc *= &a_copy;
         acc
     }
 }
@@ -114,4 +294,75 @@ mod tests {
         assert_eq!(integer_log(125, 5), Some((3, true)));
         assert_eq!(integer_log(17, 9), Some((1, false)));
     }
+
+    /// Primes of range, by trial division
+    fn trial_division(range: Range<u64>) -> Vec<u64> {
+        range
+            .filter(|&n| n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| n % d != 0))
+            .collect()
+    }
+
+    #[test]
+    fn primes_tests() {
+        assert_eq!(
+            Primes::new(0..30).collect::<Vec<_>>(),
+            trial_division(0..30)
+        );
+        assert_eq!(Primes::new(2..3).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(Primes::new(14..17).count(), 0);
+        assert_eq!(Primes::new(17..18).collect::<Vec<_>>(), vec![17]);
+        // several segments
+        assert_eq!(Primes::new(0..1_000_000).count(), 78498);
+        assert_eq!(
+            Primes::new(999_000..1_001_000).collect::<Vec<_>>(),
+            trial_division(999_000..1_001_000)
+        );
+        let start = 1_000_000_000_000;
+        assert!(
+            Primes::new(start..start + 10_000).eq((start..start + 10_000)
+                .filter(|&n| Integer::from(n).is_probably_prime(30)
+                    != crate::arith::integer::IsPrime::No))
+        );
+    }
+
+    /// Compares tables of random ranges with trial division
+    #[test]
+    fn prime_table_tests() {
+        let mut rand = RandState::new();
+        for _ in 0..200 {
+            let start = rand.bits(24) as u64 >> rand.below(24);
+            let end = start + rand.below(3000) as u64;
+            let table = PrimeTable::new(start..end);
+            let expected = trial_division(start..end);
+            for n in start..end {
+                assert_eq!(
+                    table.is_prime(n),
+                    expected.binary_search(&n).is_ok(),
+                    "{}",
+                    n
+                );
+            }
+            assert!(table.primes_in(start..end).eq(expected.iter().copied()));
+            let (a, b) = (start + (end - start) / 3, end - (end - start) / 4);
+            assert!(table
+                .primes_in(a..b)
+                .eq(expected.iter().copied().filter(|p| (a..b).contains(p))));
+        }
+        let table = PrimeTable::new(0..3);
+        assert!(!table.is_prime(0) && !table.is_prime(1) && table.is_prime(2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn prime_table_out_of_range() {
+        PrimeTable::new(100..200).is_prime(200);
+    }
+
+    #[test]
+    fn isqrt_tests() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(99), 9);
+        assert_eq!(isqrt(100), 10);
+        assert_eq!(isqrt(u64::MAX), u32::MAX as u64);
+    }
 }
@@ -1,3 +1,14 @@
+pub mod bignum;
+pub mod edwards_point;
+pub mod fixed_ring;
+#[cfg(feature = "gmp")]
+pub mod gmp_ring;
+pub mod integer;
 pub mod misc;
 pub mod modular_arithmetic;
 pub mod montgomery_point;
+pub mod poly;
+pub mod prac;
+pub mod ring;
+pub mod special_ring;
+pub mod weierstrass_point;
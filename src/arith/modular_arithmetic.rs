@@ -1,13 +1,14 @@
-use rug::ops::Pow;
-use rug::Integer;
+use crate::arith::integer::{Assign, Complete, Integer, Pow};
 
 pub fn div_mod(a: &Integer, modulo: &Integer) -> (Integer, Integer) {
-    <(Integer, Integer)>::from(a.div_rem_ref(modulo))
+    let (mut quotient, mut rem) = (Integer::new(), Integer::new());
+    (&mut quotient, &mut rem).assign(a.div_rem_ref(modulo));
+    (quotient, rem)
 }
 
 pub fn take_mod(a: &Integer, modulo: &Integer) -> Integer {
     // The second element of the tuple is the remainder of the division of a by n
-    let rem = div_mod(&a, &modulo).1;
+    let rem = div_mod(a, modulo).1;
     // Return positive remainder
     if rem < 0 {
         rem + modulo
@@ -17,19 +18,19 @@ pub fn take_mod(a: &Integer, modulo: &Integer) -> Integer {
 }
 
 pub fn multiply_mod(a: &Integer, b: &Integer, modulo: &Integer) -> Integer {
-    take_mod(&Integer::from(a * b), modulo)
+    take_mod(&(a * b).complete(), modulo)
 }
 
 pub fn add_mod(a: &Integer, b: &Integer, modulo: &Integer) -> Integer {
-    take_mod(&Integer::from(a + b), modulo)
+    take_mod(&(a + b).complete(), modulo)
 }
 
 pub fn subtract_mod(a: &Integer, b: &Integer, modulo: &Integer) -> Integer {
-    take_mod(&Integer::from(a - b), modulo)
+    take_mod(&(a - b).complete(), modulo)
 }
 
 pub fn invert_mod(a: &Integer, modulo: &Integer) -> Option<Integer> {
-    a.invert_ref(modulo).and_then(|b| Some(Integer::from(b)))
+    a.invert_ref(modulo).map(Complete::complete)
 }
 
 pub fn pow_mod(a: &Integer, n: u32, modulo: &Integer) -> Integer {
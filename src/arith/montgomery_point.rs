@@ -1,113 +1,633 @@
-use crate::arith::misc::*;
+use crate::arith::integer::{Complete, Integer};
 use crate::arith::modular_arithmetic::*;
-use rug::Integer;
-
-#[derive(Clone, PartialEq, Debug, Default)]
-pub struct MontgomeryPoint {
-    pub x: Integer,
-    pub z: Integer,
-    a24: Integer,
-    modulo: Integer,
+use crate::arith::prac::{LucasChain, PracStep};
+use crate::arith::ring::ModularRing;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+/// Montgomery curve By^2 = x^3 + Ax^2 + x mod n.
+/// Points are kept in (X:Z) coordinates, for which only the constant
+/// a24 = (A+2)/4 matters.
+///
+/// A curve only exists within the closure given to `MontgomeryCurve::with`
+/// (or `with_a24`, `with_a`), and 'c is its brand: invariant, and a
+/// different lifetime for each call, so that the points of two curves
+/// cannot be mixed.
+///
+/// The curve owns the scratch residues used by the in-place group
+/// operations (`*_into`, `*_assign`), which therefore do not allocate.
+/// A curve is meant to be used by the thread that built it.
+#[derive(Debug)]
+pub struct MontgomeryCurve<'c, R: ModularRing> {
+    ring: R,
+    a24: R::Elem,
+    scratch: RefCell<[R::Elem; 4]>,
+    brand: PhantomData<fn(&'c ()) -> &'c ()>,
 }
 
-impl MontgomeryPoint {
-    /// Montgomery Point
-    #[allow(dead_code)]
-    pub fn new(x: Integer, z: Integer, a24: Integer, modulo: Integer) -> MontgomeryPoint {
-        MontgomeryPoint { x, z, a24, modulo }
+/// Point (X:Z) on a Montgomery curve, borrowing the curve it lies on
+#[derive(Clone, Debug)]
+pub struct MontgomeryPoint<'c, R: ModularRing> {
+    pub x: R::Elem,
+    pub z: R::Elem,
+    curve: &'c MontgomeryCurve<'c, R>,
+}
+
+impl<R: ModularRing> PartialEq for MontgomeryPoint<'_, R> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.curve, other.curve) && self.x == other.x && self.z == other.z
+    }
+}
+
+impl<R: ModularRing> MontgomeryCurve<'_, R> {
+    /// f of the Montgomery curve given by a24 mod modulo
+    pub fn with_a24<T>(
+        a24: Integer,
+        modulo: Integer,
+        f: impl for<'c> FnOnce(&'c MontgomeryCurve<'c, R>) -> T,
+    ) -> T {
+        let ring = R::new(&modulo).unwrap();
+        MontgomeryCurve::with(ring.to_residue(&a24), ring, f)
     }
 
-    pub fn new2(x: Integer, z: Integer, a: Integer, modulo: Integer) -> MontgomeryPoint {
+    /// f of the Montgomery curve given by its coefficient A mod modulo
+    pub fn with_a<T>(
+        a: Integer,
+        modulo: Integer,
+        f: impl for<'c> FnOnce(&'c MontgomeryCurve<'c, R>) -> T,
+    ) -> T {
         let inv = invert_mod(&Integer::from(4), &modulo).unwrap();
-        MontgomeryPoint {
-            x,
-            z,
-            a24: multiply_mod(&Integer::from(&a + 2), &inv, &modulo),
-            modulo,
+        let a24 = multiply_mod(&(&a + 2u32).complete(), &inv, &modulo);
+        MontgomeryCurve::with_a24(a24, modulo, f)
+    }
+
+    /// f of the Montgomery curve whose a24 is already a residue of ring.
+    /// f gets the curve for any brand 'c, so the points it builds on the
+    /// curve can only be combined with each other:
+    ///
+    /// ```compile_fail
+    /// use ecm::arith::montgomery_point::MontgomeryCurve;
+    /// use ecm::arith::ring::DefaultRing;
+    /// use ecm::Integer;
+    ///
+    /// let (a, b, n) = (Integer::from(7), Integer::from(9), Integer::from(29));
+    /// MontgomeryCurve::<DefaultRing>::with_a24(a, n.clone(), |c1| {
+    ///     let p = c1.point(&Integer::from(11), &Integer::from(16));
+    ///     MontgomeryCurve::<DefaultRing>::with_a24(b, n, |c2| {
+    ///         c2.double(&p);
+    ///     });
+    /// });
+    /// ```
+    pub fn with<T>(
+        a24: R::Elem,
+        ring: R,
+        f: impl for<'c> FnOnce(&'c MontgomeryCurve<'c, R>) -> T,
+    ) -> T {
+        let scratch = RefCell::new([ring.zero(), ring.zero(), ring.zero(), ring.zero()]);
+        let curve = MontgomeryCurve {
+            ring,
+            a24,
+            scratch,
+            brand: PhantomData,
+        };
+        f(&curve)
+    }
+}
+
+impl<'c, R: ModularRing> MontgomeryCurve<'c, R> {
+    pub fn ring(&self) -> &R {
+        &self.ring
+    }
+
+    pub fn a24(&self) -> &R::Elem {
+        &self.a24
+    }
+
+    /// Point (X:Z) on this curve
+    pub fn point(&'c self, x: &Integer, z: &Integer) -> MontgomeryPoint<'c, R> {
+        self.point_from_residues(self.ring.to_residue(x), self.ring.to_residue(z))
+    }
+
+    /// Point whose coordinates are already residues of the curve's ring
+    pub fn point_from_residues(&'c self, x: R::Elem, z: R::Elem) -> MontgomeryPoint<'c, R> {
+        MontgomeryPoint { x, z, curve: self }
+    }
+
+    /// The point (0:0), to be overwritten by the in-place operations
+    pub fn zero_point(&'c self) -> MontgomeryPoint<'c, R> {
+        self.point_from_residues(self.ring.zero(), self.ring.zero())
+    }
+
+    /// The brand keeps out the points of other curves, this is a backstop
+    fn check_points(&self, points: &[&MontgomeryPoint<'c, R>]) {
+        debug_assert!(
+            points.iter().all(|p| std::ptr::eq(p.curve, self)),
+            "points lie on another curve"
+        );
+    }
+
+    /// Leaves in t[2] and t[3] the squares that make up p + q,
+    /// up to the factors diff.z and diff.x
+    fn addh_squares(&self, t: &mut [R::Elem; 4], p: &MontgomeryPoint<R>, q: &MontgomeryPoint<R>) {
+        let ring = &self.ring;
+        let [t0, t1, t2, t3] = t;
+        ring.sub_into(t0, &p.x, &p.z);
+        ring.add_into(t1, &q.x, &q.z);
+        ring.mul_assign(t0, t1);
+        ring.add_into(t1, &p.x, &p.z);
+        ring.sub_into(t2, &q.x, &q.z);
+        ring.mul_assign(t1, t2);
+        ring.add_into(t2, t0, t1);
+        ring.sub_into(t3, t0, t1);
+        ring.square_assign(t2);
+        ring.square_assign(t3);
+    }
+
+    /// out = p + q, where diff = p - q
+    pub fn addh_into(
+        &'c self,
+        out: &mut MontgomeryPoint<'c, R>,
+        p: &MontgomeryPoint<'c, R>,
+        q: &MontgomeryPoint<'c, R>,
+        diff: &MontgomeryPoint<'c, R>,
+    ) {
+        self.check_points(&[out, p, q, diff]);
+        let mut t = self.scratch.borrow_mut();
+        self.addh_squares(&mut t, p, q);
+        self.ring.mul_into(&mut out.x, &diff.z, &t[2]);
+        self.ring.mul_into(&mut out.z, &diff.x, &t[3]);
+    }
+
+    /// p = p + q, where diff = p - q
+    pub fn addh_assign(
+        &'c self,
+        p: &mut MontgomeryPoint<'c, R>,
+        q: &MontgomeryPoint<'c, R>,
+        diff: &MontgomeryPoint<'c, R>,
+    ) {
+        self.check_points(&[p, q, diff]);
+        let mut t = self.scratch.borrow_mut();
+        self.addh_squares(&mut t, p, q);
+        self.ring.mul_into(&mut p.x, &diff.z, &t[2]);
+        self.ring.mul_into(&mut p.z, &diff.x, &t[3]);
+    }
+
+    pub fn addh(
+        &'c self,
+        p: &MontgomeryPoint<'c, R>,
+        q: &MontgomeryPoint<'c, R>,
+        diff: &MontgomeryPoint<'c, R>,
+    ) -> MontgomeryPoint<'c, R> {
+        let mut out = self.zero_point();
+        self.addh_into(&mut out, p, q, diff);
+        out
+    }
+
+    /// Leaves in t[0] and t[1] the X and Z coordinates of [2]p
+    fn double_residues(&self, t: &mut [R::Elem; 4], p: &MontgomeryPoint<R>) {
+        let ring = &self.ring;
+        let [t0, t1, t2, t3] = t;
+        ring.add_into(t2, &p.x, &p.z);
+        ring.square_assign(t2);
+        ring.sub_into(t3, &p.x, &p.z);
+        ring.square_assign(t3);
+        ring.mul_into(t0, t2, t3);
+        ring.sub_assign(t2, t3);
+        ring.mul_into(t1, &self.a24, t2);
+        ring.add_assign(t1, t3);
+        ring.mul_assign(t1, t2);
+    }
+
+    /// out = [2]p, requires five multiplications
+    pub fn double_into(&'c self, out: &mut MontgomeryPoint<'c, R>, p: &MontgomeryPoint<'c, R>) {
+        self.check_points(&[out, p]);
+        let mut t = self.scratch.borrow_mut();
+        self.double_residues(&mut t, p);
+        std::mem::swap(&mut out.x, &mut t[0]);
+        std::mem::swap(&mut out.z, &mut t[1]);
+    }
+
+    /// p = [2]p
+    pub fn double_assign(&'c self, p: &mut MontgomeryPoint<'c, R>) {
+        self.check_points(&[p]);
+        let mut t = self.scratch.borrow_mut();
+        self.double_residues(&mut t, p);
+        std::mem::swap(&mut p.x, &mut t[0]);
+        std::mem::swap(&mut p.z, &mut t[1]);
+    }
+
+    /// Doubles a point in Montgomery form, requires five multiplications
+    pub fn double(&'c self, p: &MontgomeryPoint<'c, R>) -> MontgomeryPoint<'c, R> {
+        let mut out = self.zero_point();
+        self.double_into(&mut out, p);
+        out
+    }
+
+    /// out = [k]p using Montgomery's ladder, for k > 0
+    pub fn montgomery_ladder_into(
+        &'c self,
+        out: &mut MontgomeryPoint<'c, R>,
+        p: &MontgomeryPoint<'c, R>,
+        k: &Integer,
+    ) {
+        let mut r = self.zero_point();
+        self.ladder_pair_into(out, &mut r, p, k);
+    }
+
+    /// out = [k]p and r = [k + 1]p using Montgomery's ladder, for k > 0
+    fn ladder_pair_into(
+        &'c self,
+        out: &mut MontgomeryPoint<'c, R>,
+        r: &mut MontgomeryPoint<'c, R>,
+        p: &MontgomeryPoint<'c, R>,
+        k: &Integer,
+    ) {
+        out.clone_from(p);
+        self.double_into(r, p);
+        // Invariant: r - out = p
+        for i in (0..k.significant_bits().saturating_sub(1)).rev() {
+            if k.get_bit(i) {
+                self.addh_assign(out, r, p);
+                self.double_assign(r);
+            } else {
+                self.addh_assign(r, out, p);
+                self.double_assign(out);
+            }
+        }
+    }
+
+    /// p = [k]p along the Lucas chain for k, using five scratch points
+    /// of this curve.
+    ///
+    /// A, B and C stand for p and the scratch points b and c, with the
+    /// invariant C = A - B; t, t2 and u hold intermediate results.
+    pub fn lucas_chain_assign(
+        &'c self,
+        p: &mut MontgomeryPoint<'c, R>,
+        chain: &LucasChain,
+        scratch: &mut [MontgomeryPoint<'c, R>; 5],
+    ) {
+        let a = p;
+        let [b, c, t, t2, u] = scratch;
+        b.clone_from(a);
+        c.clone_from(a);
+        self.double_assign(a);
+        for step in chain.steps() {
+            match step {
+                PracStep::Swap => std::mem::swap(a, b),
+                PracStep::Rule(1) => {
+                    self.addh_into(t, a, b, c);
+                    self.addh_into(t2, t, a, b);
+                    self.addh_assign(b, t, a);
+                    std::mem::swap(a, t2);
+                }
+                PracStep::Rule(2) | PracStep::Rule(4) => {
+                    self.addh_assign(b, a, c);
+                    self.double_assign(a);
+                }
+                PracStep::Rule(3) => {
+                    self.addh_into(t, b, a, c);
+                    // (B, T, C) <- (T, C, B)
+                    std::mem::swap(b, t);
+                    std::mem::swap(t, c);
+                }
+                PracStep::Rule(5) => {
+                    self.addh_assign(c, a, b);
+                    self.double_assign(a);
+                }
+                PracStep::Rule(6) => {
+                    self.double_into(t, a);
+                    self.addh_into(t2, a, b, c);
+                    self.addh_into(u, t, a, a);
+                    std::mem::swap(a, u);
+                    self.addh_assign(t, t2, c);
+                    // (C, B, T) <- (B, T, C)
+                    std::mem::swap(c, b);
+                    std::mem::swap(b, t);
+                }
+                PracStep::Rule(7) => {
+                    self.addh_into(t, a, b, c);
+                    self.addh_into(u, t, a, b);
+                    std::mem::swap(b, u);
+                    self.double_into(t, a);
+                    self.addh_into(u, a, t, a);
+                    std::mem::swap(a, u);
+                }
+                PracStep::Rule(8) => {
+                    self.addh_into(t, a, b, c);
+                    self.addh_assign(c, a, b);
+                    std::mem::swap(b, t);
+                    self.double_into(t, a);
+                    self.addh_into(u, a, t, a);
+                    std::mem::swap(a, u);
+                }
+                PracStep::Rule(9) => {
+                    self.addh_assign(c, b, a);
+                    self.double_assign(b);
+                }
+                PracStep::Rule(r) => unreachable!("no PRAC rule {}", r),
+            }
         }
+        self.addh_assign(a, b, c);
+    }
+
+    /// Scalar multiplication in Montgomery form
+    pub fn montgomery_ladder(
+        &'c self,
+        p: &MontgomeryPoint<'c, R>,
+        k: &Integer,
+    ) -> MontgomeryPoint<'c, R> {
+        let mut out = self.zero_point();
+        self.montgomery_ladder_into(&mut out, p, k);
+        out
+    }
+
+    /// ([k]p, [k + 1]p) using Montgomery's ladder, for k > 0: what
+    /// `FullCurve::recover_y` needs to give [k]p its y
+    pub fn montgomery_ladder_pair(
+        &'c self,
+        p: &MontgomeryPoint<'c, R>,
+        k: &Integer,
+    ) -> (MontgomeryPoint<'c, R>, MontgomeryPoint<'c, R>) {
+        let mut out = self.zero_point();
+        let mut r = self.zero_point();
+        self.ladder_pair_into(&mut out, &mut r, p, k);
+        (out, r)
+    }
+}
+
+impl<'c, R: ModularRing> MontgomeryPoint<'c, R> {
+    pub fn curve(&self) -> &'c MontgomeryCurve<'c, R> {
+        self.curve
+    }
+
+    /// Coordinates (X, Z) converted back from Montgomery representation
+    pub fn coordinates(&self) -> (Integer, Integer) {
+        let ring = &self.curve.ring;
+        (ring.to_integer(&self.x), ring.to_integer(&self.z))
     }
 
     /// Two points are equal if their ratio x.z^{-1} are congruent mod n
     #[allow(dead_code)]
-    pub fn equals(&self, other: &MontgomeryPoint) -> bool {
-        if !self.modulo.eq(&other.modulo) || !self.a24.eq(&other.a24) {
-            false;
+    pub fn equals(&self, other: &MontgomeryPoint<'c, R>) -> bool {
+        if !std::ptr::eq(self.curve, other.curve) {
+            return false;
         }
+        let ring = &self.curve.ring;
         // Compute the inverse of z mod n...
-        let self_z_inverse = invert_mod(&self.z, &self.modulo);
-        let other_z_inverse = invert_mod(&other.z, &other.modulo);
+        let self_z_inverse = ring.invert(&self.z);
+        let other_z_inverse = ring.invert(&other.z);
         // ... provided it exists:
         match (self_z_inverse, other_z_inverse) {
             (Some(self_z_inv), Some(other_z_inv)) => {
-                let self_ratio = multiply_mod(&self.x, &self_z_inv, &self.modulo);
-                let other_ratio = multiply_mod(&other.x, &other_z_inv, &self.modulo);
-                self_ratio == other_ratio // compare ratios x.z^{-1} mod n
+                let self_ratio = ring.mul(&self.x, &self_z_inv);
+                let other_ratio = ring.mul(&other.x, &other_z_inv);
+                // compare ratios x.z^{-1} mod n
+                ring.to_integer(&self_ratio) == ring.to_integer(&other_ratio)
             }
             _ => false, // z isn't invertible mod n
         }
     }
+}
+
+/// Multiplications in an addition of `FullCurve`
+pub(crate) const FULL_ADD: u64 = 24;
+
+/// The curve By^2 = x^3 + Ax^2 + x of a `MontgomeryCurve`, with B chosen
+/// so that a given point has y = 1, on which points have all three
+/// projective coordinates (X:Y:Z).
+///
+/// (X:Z) arithmetic does not depend on B, and the curves for the various
+/// B are twists of each other: lifting a point of stage 1 this way gives
+/// a point of the same order, that can be added to any other point and
+/// not only to those whose difference is known.
+#[derive(Clone, Debug)]
+pub struct FullCurve<R: ModularRing> {
+    ring: R,
+    a: R::Elem,
+    b: R::Elem,
+    scratch: RefCell<[R::Elem; 8]>,
+}
 
-    pub fn addh(&self, other: &MontgomeryPoint, diff: &MontgomeryPoint) -> MontgomeryPoint {
-        // diff = self - other
-        // TODO: Check a24 and modulo
-        let self_x_min_z = subtract_mod(&self.x, &self.z, &self.modulo);
-        let self_x_plus_z = add_mod(&self.x, &self.z, &self.modulo);
+/// Point (X:Y:Z) on a `FullCurve`, never the point at infinity
+#[derive(Clone, PartialEq, Debug)]
+pub struct FullPoint<R: ModularRing> {
+    pub x: R::Elem,
+    pub y: R::Elem,
+    pub z: R::Elem,
+}
+
+impl<'c, R: ModularRing> MontgomeryCurve<'c, R> {
+    /// The curve through p with y = 1, and p on it. Normalizing p takes
+    /// an inversion: if Z is not invertible, returns gcd(Z, n).
+    pub fn lift(
+        &self,
+        p: &MontgomeryPoint<'c, R>,
+    ) -> Result<(FullCurve<R>, FullPoint<R>), Integer> {
+        let ring = &self.ring;
+        let inv = ring.invert(&p.z).ok_or_else(|| ring.gcd(&p.z))?;
+        let x = ring.mul(&p.x, &inv);
+        // A = 4 a24 - 2
+        let two = ring.add(&ring.one(), &ring.one());
+        let a2 = ring.add(&self.a24, &self.a24);
+        let mut a = ring.add(&a2, &a2);
+        ring.sub_assign(&mut a, &two);
+        // B = x^3 + A x^2 + x
+        let mut b = ring.add(&x, &a);
+        ring.mul_assign(&mut b, &x);
+        ring.add_assign(&mut b, &ring.one());
+        ring.mul_assign(&mut b, &x);
+        let curve = FullCurve::with_ring(a, b, ring.clone());
+        let point = FullPoint {
+            x,
+            y: ring.one(),
+            z: ring.one(),
+        };
+        Ok((curve, point))
+    }
+}
 
-        let other_x_min_z = subtract_mod(&other.x, &other.z, &self.modulo);
-        let other_x_plus_z = add_mod(&other.x, &other.z, &self.modulo);
+impl<R: ModularRing> FullCurve<R> {
+    /// The curve By^2 = x^3 + Ax^2 + x whose A and B are residues of ring
+    pub fn with_ring(a: R::Elem, b: R::Elem, ring: R) -> FullCurve<R> {
+        let scratch = RefCell::new([(); 8].map(|_| ring.zero()));
+        FullCurve {
+            ring,
+            a,
+            b,
+            scratch,
+        }
+    }
 
-        let prod1 = multiply_mod(&self_x_min_z, &other_x_plus_z, &self.modulo);
-        let prod2 = multiply_mod(&self_x_plus_z, &other_x_min_z, &self.modulo);
+    /// a24 = (A + 2)/4 of the curve on x only
+    pub fn a24(&self) -> R::Elem {
+        let ring = &self.ring;
+        let two = ring.add(&ring.one(), &ring.one());
+        // 4 is invertible, n being odd
+        let quarter = ring.invert(&ring.add(&two, &two)).unwrap();
+        ring.mul(&ring.add(&self.a, &two), &quarter)
+    }
 
-        let addition = add_mod(&prod1, &prod2, &self.modulo);
-        let subtraction = subtract_mod(&prod1, &prod2, &self.modulo);
+    pub fn ring(&self) -> &R {
+        &self.ring
+    }
 
-        let sqr1 = multiply_mod(&addition, &addition, &self.modulo);
-        let sqr2 = multiply_mod(&subtraction, &subtraction, &self.modulo);
+    pub fn a(&self) -> &R::Elem {
+        &self.a
+    }
 
-        MontgomeryPoint {
-            x: multiply_mod(&diff.z, &sqr1, &self.modulo),
-            z: multiply_mod(&diff.x, &sqr2, &self.modulo),
-            a24: self.a24.clone(),
-            modulo: self.modulo.clone(),
+    pub fn b(&self) -> &R::Elem {
+        &self.b
+    }
+
+    /// Whether B Y^2 Z = X^3 + A X^2 Z + X Z^2
+    pub fn is_on_curve(&self, p: &FullPoint<R>) -> bool {
+        let ring = &self.ring;
+        let mut lhs = ring.square(&p.y);
+        ring.mul_assign(&mut lhs, &self.b);
+        ring.mul_assign(&mut lhs, &p.z);
+        // ((X + A Z) X + Z^2) X
+        let mut rhs = ring.mul(&self.a, &p.z);
+        ring.add_assign(&mut rhs, &p.x);
+        ring.mul_assign(&mut rhs, &p.x);
+        ring.add_assign(&mut rhs, &ring.square(&p.z));
+        ring.mul_assign(&mut rhs, &p.x);
+        ring.is_zero(&ring.sub(&lhs, &rhs))
+    }
+
+    /// out = p + lambda-line through p, for the slope lambda = t[0] / t[1]
+    /// and x-coordinates summing to t[3] / t[2]: the chord and tangent
+    /// formulas with everything over the common denominator v^3 W Z_p.
+    fn finish(&self, t: &mut [R::Elem; 8], out: &mut FullPoint<R>, p: &FullPoint<R>) {
+        let ring = &self.ring;
+        let [u, v, w, s, v2, v3, c, tmp] = t;
+        ring.square_into(v2, v);
+        ring.mul_into(v3, v2, v);
+        // c = W (B u^2 - A v^2) - v^2 S, so that x_out = c / (v^2 W)
+        ring.square_into(c, u);
+        ring.mul_assign(c, &self.b);
+        ring.mul_into(tmp, &self.a, v2);
+        ring.sub_assign(c, tmp);
+        ring.mul_assign(c, w);
+        ring.mul_assign(s, v2);
+        ring.sub_assign(c, s);
+        // Y = u (X_p v^2 W - c Z_p) - Y_p v^3 W
+        ring.mul_into(tmp, &p.x, v2);
+        ring.mul_assign(tmp, w);
+        ring.mul_into(s, c, &p.z);
+        ring.sub_assign(tmp, s);
+        ring.mul_into(&mut out.y, u, tmp);
+        ring.mul_assign(v3, w);
+        ring.mul_into(tmp, &p.y, v3);
+        ring.sub_assign(&mut out.y, tmp);
+        // X = c v Z_p, Z = v^3 W Z_p
+        ring.mul_into(&mut out.x, c, v);
+        ring.mul_assign(&mut out.x, &p.z);
+        ring.mul_into(&mut out.z, v3, &p.z);
+    }
+
+    /// out = p + q, for p != +-q
+    pub fn add_into(&self, out: &mut FullPoint<R>, p: &FullPoint<R>, q: &FullPoint<R>) {
+        let ring = &self.ring;
+        let t = &mut *self.scratch.borrow_mut();
+        {
+            let [u, v, w, s, _, _, c, tmp] = &mut *t;
+            ring.mul_into(u, &q.y, &p.z);
+            ring.mul_into(tmp, &p.y, &q.z);
+            ring.sub_assign(u, tmp);
+            ring.mul_into(c, &p.x, &q.z);
+            ring.mul_into(v, &q.x, &p.z);
+            ring.add_into(s, v, c);
+            ring.sub_assign(v, c);
+            ring.mul_into(w, &p.z, &q.z);
         }
+        self.finish(t, out, p);
     }
 
-    /// Doubles a point in Montgomery form, requires five multiplications
-    pub fn double(&self) -> MontgomeryPoint {
-        let self_x_plus_z = add_mod(&self.x, &self.z, &self.modulo);
-        let self_x_min_z = subtract_mod(&self.x, &self.z, &self.modulo);
-
-        let u = self_x_plus_z.square();
-        let v = self_x_min_z.square();
-
-        let diff = Integer::from(&u - &v);
-        let x = multiply_mod(&u, &v, &self.modulo);
-        let z = take_mod(
-            &Integer::from(&diff * Integer::from(&v + &self.a24 * &diff)),
-            &self.modulo,
-        );
+    /// out = [2]p
+    pub fn double_into(&self, out: &mut FullPoint<R>, p: &FullPoint<R>) {
+        let ring = &self.ring;
+        let t = &mut *self.scratch.borrow_mut();
+        {
+            let [u, v, w, s, _, _, _, tmp] = &mut *t;
+            // u = 3X^2 + 2AXZ + Z^2, v = 2BYZ
+            ring.add_into(s, &p.x, &p.x);
+            ring.mul_into(tmp, &self.a, &p.z);
+            ring.add_into(u, s, &p.x);
+            ring.add_assign(u, tmp);
+            ring.add_assign(u, tmp);
+            ring.mul_assign(u, &p.x);
+            ring.square_into(tmp, &p.z);
+            ring.add_assign(u, tmp);
+            ring.mul_into(tmp, &self.b, &p.y);
+            ring.mul_assign(tmp, &p.z);
+            ring.add_into(v, tmp, tmp);
+            w.clone_from(&p.z);
+        }
+        self.finish(t, out, p);
+    }
 
-        MontgomeryPoint {
-            x,
-            z,
-            a24: Integer::from(&self.a24),
-            modulo: Integer::from(&self.modulo),
+    /// [k]p from q = [k]p and r = [k + 1]p on x only, as the ladder gives
+    /// them, by the formula of Okeya and Sakurai (CHES 2001). The result
+    /// has Z = 0 if q or r is zero, or if p has order 2.
+    pub fn recover_y(
+        &self,
+        p: &FullPoint<R>,
+        q: &MontgomeryPoint<R>,
+        r: &MontgomeryPoint<R>,
+    ) -> FullPoint<R> {
+        let ring = &self.ring;
+        // For p = (x, y) and q = (X:Z): Y = Z_r ((X + xZ + 2AZ)(xX + Z)
+        // - 2AZ^2) - (X - xZ)^2 X_r over 2By Z Z_r, with x = X_p/Z_p and
+        // y = Y_p/Z_p, everything multiplied by Z_p^2
+        let xz = ring.mul(&q.x, &p.z);
+        let zx = ring.mul(&q.z, &p.x);
+        let zz = ring.mul(&q.z, &p.z);
+        let a2zz = ring.mul(&ring.add(&self.a, &self.a), &zz);
+        let mut y = ring.add(&xz, &zx);
+        ring.add_assign(&mut y, &a2zz);
+        let mut t = ring.mul(&q.x, &p.x);
+        ring.add_assign(&mut t, &zz);
+        ring.mul_assign(&mut y, &t);
+        ring.mul_into(&mut t, &a2zz, &zz);
+        ring.sub_assign(&mut y, &t);
+        ring.mul_assign(&mut y, &r.z);
+        ring.sub_into(&mut t, &xz, &zx);
+        ring.square_assign(&mut t);
+        ring.mul_assign(&mut t, &r.x);
+        ring.sub_assign(&mut y, &t);
+        // 2B Y_p Z_p Z Z_r
+        let by = ring.mul(&self.b, &p.y);
+        let mut c = ring.add(&by, &by);
+        ring.mul_assign(&mut c, &zz);
+        ring.mul_assign(&mut c, &r.z);
+        FullPoint {
+            x: ring.mul(&c, &q.x),
+            y,
+            z: ring.mul(&c, &q.z),
         }
     }
 
-    /// Scalar multiplication in Montgomery form
-    pub fn montgomery_ladder(&self, k: &Integer) -> MontgomeryPoint {
-        let mut q = self.clone();
-        let mut p = self.double();
-        let bv = bits(k);
-        for b in 1..bv.len() {
-            if bv[b] == '1' {
-                q = p.addh(&q, &self);
-                p = p.double();
-            } else {
-                p = q.addh(&p, &self);
-                q = q.double();
+    /// [k]p for k >= 1 by the ladder on x only, y being recovered at the end
+    pub fn ladder(&self, p: &FullPoint<R>, k: &Integer) -> FullPoint<R> {
+        MontgomeryCurve::with(self.a24(), self.ring.clone(), |curve| {
+            let xz = curve.point_from_residues(p.x.clone(), p.z.clone());
+            let (q, r) = curve.montgomery_ladder_pair(&xz, k);
+            self.recover_y(p, &q, &r)
+        })
+    }
+
+    /// [k]p by double and add, for k >= 1
+    pub fn mul(&self, p: &FullPoint<R>, k: &Integer) -> FullPoint<R> {
+        assert!(*k >= 1, "k must be positive");
+        let mut q = p.clone();
+        let mut tmp = p.clone();
+        for i in (0..k.significant_bits() - 1).rev() {
+            self.double_into(&mut tmp, &q);
+            std::mem::swap(&mut q, &mut tmp);
+            if k.get_bit(i) {
+                self.add_into(&mut tmp, &q, p);
+                std::mem::swap(&mut q, &mut tmp);
             }
         }
         q
@@ -117,64 +637,40 @@ impl MontgomeryPoint {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::arith::ring::DefaultRing;
 
     #[test]
     fn montgomery_addh_tests() {
-        let p1 = MontgomeryPoint::new(
-            Integer::from(11),
-            Integer::from(16),
-            Integer::from(7),
-            Integer::from(29),
-        );
-        let p2 = MontgomeryPoint::new(
-            Integer::from(13),
-            Integer::from(10),
-            Integer::from(7),
-            Integer::from(29),
-        );
-        let p3 = p2.addh(&p1, &p1);
-        assert_eq!((p3.x, p3.z), (Integer::from(23), Integer::from(17)));
+        MontgomeryCurve::<DefaultRing>::with_a24(Integer::from(7), Integer::from(29), |curve| {
+            let p1 = curve.point(&Integer::from(11), &Integer::from(16));
+            let p2 = curve.point(&Integer::from(13), &Integer::from(10));
+            let p3 = curve.addh(&p2, &p1, &p1);
+            assert_eq!(p3.coordinates(), (Integer::from(23), Integer::from(17)));
+        });
     }
 
     #[test]
     fn montgomery_double_tests() {
-        let p = MontgomeryPoint::new(
-            Integer::from(11),
-            Integer::from(16),
-            Integer::from(7),
-            Integer::from(29),
-        );
-        let q = p.double();
-        assert_eq!((q.x, q.z), (Integer::from(13), Integer::from(10)));
+        MontgomeryCurve::<DefaultRing>::with_a24(Integer::from(7), Integer::from(29), |curve| {
+            let p = curve.point(&Integer::from(11), &Integer::from(16));
+            let q = curve.double(&p);
+            assert_eq!(q.coordinates(), (Integer::from(13), Integer::from(10)));
+        });
 
-        let p1 = MontgomeryPoint::new2(
-            Integer::from(10),
-            Integer::from(17),
-            Integer::from(10),
-            Integer::from(101),
-        );
-        let p2 = p1.double();
-        assert_eq!(
-            p2,
-            MontgomeryPoint::new2(
-                Integer::from(68),
-                Integer::from(56),
-                Integer::from(10),
-                Integer::from(101)
-            )
-        );
+        MontgomeryCurve::<DefaultRing>::with_a(Integer::from(10), Integer::from(101), |curve| {
+            let p1 = curve.point(&Integer::from(10), &Integer::from(17));
+            let p2 = curve.double(&p1);
+            assert_eq!(p2, curve.point(&Integer::from(68), &Integer::from(56)));
+        });
     }
 
     #[test]
     fn montgomery_ladder_tests() {
-        let p = MontgomeryPoint::new(
-            Integer::from(11),
-            Integer::from(16),
-            Integer::from(7),
-            Integer::from(29),
-        );
-        let q = p.montgomery_ladder(&Integer::from(3));
-        assert_eq!((q.x, q.z), (Integer::from(23), Integer::from(17)));
+        MontgomeryCurve::<DefaultRing>::with_a24(Integer::from(7), Integer::from(29), |curve| {
+            let p = curve.point(&Integer::from(11), &Integer::from(16));
+            let q = curve.montgomery_ladder(&p, &Integer::from(3));
+            assert_eq!(q.coordinates(), (Integer::from(23), Integer::from(17)));
+        });
     }
 
     #[test]
@@ -188,28 +684,120 @@ mod tests {
             &invert_mod(&Integer::from(4), &modulo).unwrap(),
             &modulo,
         );
-        let a24_1 = Integer::from(&a24);
-        let a24_2 = Integer::from(&a24);
-        let p1 = MontgomeryPoint {
-            x,
-            z,
-            a24: a24_1,
-            modulo,
-        };
+        let from_a24 = MontgomeryCurve::<DefaultRing>::with_a24(a24, modulo, |curve| {
+            let p1 = curve.point(&x, &z);
 
-        let mod_2 = Integer::from(101);
-        let x1 = Integer::from(68);
-        let z1 = Integer::from(56);
-        let p2 = p1.double();
-
-        assert_eq!(
-            p2,
-            MontgomeryPoint {
-                x: x1,
-                z: z1,
-                a24: a24_2,
-                modulo: mod_2
-            }
+            let x1 = Integer::from(68);
+            let z1 = Integer::from(56);
+            let p2 = curve.double(&p1);
+
+            assert_eq!(p2, curve.point(&x1, &z1));
+            curve.a24().clone()
+        });
+        let from_a = MontgomeryCurve::<DefaultRing>::with_a(a, Integer::from(101), |curve| {
+            curve.a24().clone()
+        });
+        assert_eq!(from_a24, from_a);
+    }
+
+    #[test]
+    fn montgomery_equals_tests() {
+        MontgomeryCurve::<DefaultRing>::with_a24(Integer::from(7), Integer::from(29), |curve| {
+            let p = curve.point(&Integer::from(11), &Integer::from(16));
+            // (22:32) is the same projective point as (11:16)
+            assert!(p.equals(&curve.point(&Integer::from(22), &Integer::from(32))));
+            assert!(!p.equals(&curve.point(&Integer::from(13), &Integer::from(10))));
+        });
+    }
+
+    #[test]
+    fn montgomery_in_place_tests() {
+        MontgomeryCurve::<DefaultRing>::with_a(
+            Integer::from(10),
+            Integer::from(1000003),
+            |curve| {
+                let p = curve.point(&Integer::from(10), &Integer::from(17));
+                let p2 = curve.double(&p);
+                let mut p3 = p2.clone();
+                curve.addh_assign(&mut p3, &p, &p);
+                assert_eq!(p3, curve.addh(&p2, &p, &p));
+
+                // [12]p = [2][2][3]p
+                let mut q = curve.zero_point();
+                curve.montgomery_ladder_into(&mut q, &p, &Integer::from(12));
+                let mut r = curve.zero_point();
+                curve.double_into(&mut r, &p3);
+                curve.double_assign(&mut r);
+                assert!(q.equals(&r));
+            },
+        );
+    }
+
+    #[test]
+    fn full_curve_tests() {
+        MontgomeryCurve::<DefaultRing>::with_a(
+            Integer::from(10),
+            Integer::from(1000003),
+            |curve| {
+                let p = curve.point(&Integer::from(10), &Integer::from(17));
+                let (full, fp) = curve.lift(&p).unwrap();
+                assert!(full.is_on_curve(&fp));
+                let mut sum = fp.clone();
+                for k in [2u32, 3, 12, 1000, 123456789] {
+                    let q = full.mul(&fp, &Integer::from(k));
+                    assert!(full.is_on_curve(&q), "k={}", k);
+                    let xz = curve.point_from_residues(q.x.clone(), q.z.clone());
+                    assert!(
+                        xz.equals(&curve.montgomery_ladder(&p, &Integer::from(k))),
+                        "k={}",
+                        k
+                    );
+                }
+                // p + [2]p + [4]p + [8]p = [15]p
+                let mut term = fp.clone();
+                let mut tmp = fp.clone();
+                for _ in 0..3 {
+                    full.double_into(&mut tmp, &term);
+                    std::mem::swap(&mut term, &mut tmp);
+                    full.add_into(&mut tmp, &sum, &term);
+                    std::mem::swap(&mut sum, &mut tmp);
+                }
+                assert!(full.is_on_curve(&sum));
+                let sum = curve.point_from_residues(sum.x, sum.z);
+                assert!(sum.equals(&curve.montgomery_ladder(&p, &Integer::from(15))));
+            },
+        );
+    }
+
+    /// The ladder with y recovered agrees with double and add, and the
+    /// curve on x only is the one lifted
+    #[test]
+    fn full_curve_ladder_tests() {
+        MontgomeryCurve::<DefaultRing>::with_a(
+            Integer::from(10),
+            Integer::from(1000003),
+            |curve| {
+                let p = curve.point(&Integer::from(10), &Integer::from(17));
+                let (full, fp) = curve.lift(&p).unwrap();
+                assert_eq!(&full.a24(), curve.a24());
+                let ring = full.ring();
+                // not normalized, for the Z_p of the formula
+                let seven = ring.to_residue(&Integer::from(7));
+                let fp7 = FullPoint {
+                    x: ring.mul(&fp.x, &seven),
+                    y: ring.mul(&fp.y, &seven),
+                    z: seven.clone(),
+                };
+                for k in [1u32, 2, 3, 12, 1000, 123456789] {
+                    let k = Integer::from(k);
+                    let expected = full.mul(&fp, &k);
+                    for q in [full.ladder(&fp, &k), full.ladder(&fp7, &k)] {
+                        assert!(full.is_on_curve(&q), "k={}", k);
+                        assert_eq!(ring.mul(&q.x, &expected.z), ring.mul(&expected.x, &q.z));
+                        assert_eq!(ring.mul(&q.y, &expected.z), ring.mul(&expected.y, &q.z));
+                    }
+                }
+            },
         );
     }
 }
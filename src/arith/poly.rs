@@ -0,0 +1,800 @@
+//! Polynomials over Z/nZ, as vectors of coefficients in [0, n), lowest
+//! degree first: fast multiplication, division by monic polynomials,
+//! product trees and multipoint evaluation.
+use crate::arith::fixed_ring::neg_inverse;
+use crate::arith::integer::{Complete, Integer, Order};
+use crate::arith::modular_arithmetic::take_mod;
+
+/// Below this length of the shorter factor, products are schoolbook
+const KARATSUBA_THRESHOLD: usize = 32;
+/// From this length on, Toom-3 rather than Karatsuba
+const TOOM3_THRESHOLD: usize = 128;
+/// Products go through number theoretic transforms from this length on
+/// per NTT prime they need: the CRT costs a number of multiplications
+/// quadratic in the number of primes for each coefficient
+const NTT_THRESHOLD_PER_PRIME: usize = 12;
+/// Below this degree, divisions are schoolbook
+const DIVISION_THRESHOLD: usize = 32;
+
+/// Every NTT prime is c.2^NTT_LOG + 1 < 2^62, which allows transforms of
+/// up to 2^NTT_LOG points
+const NTT_LOG: u32 = 32;
+
+/// Arithmetic modulo a prime p < 2^62 in Montgomery form, R = 2^64, and
+/// a primitive 2^NTT_LOG-th root of unity mod p
+#[derive(Clone, PartialEq, Debug)]
+struct NttPrime {
+    p: u64,
+    /// p^{-1} mod 2^64
+    p_inv: u64,
+    /// R^2 mod p
+    r2: u64,
+    /// The root of unity, in Montgomery form
+    root: u64,
+}
+
+impl NttPrime {
+    fn new(p: u64) -> NttPrime {
+        let r = (1u128 << 64) % p as u128;
+        let mut prime = NttPrime {
+            p,
+            p_inv: neg_inverse(p).wrapping_neg(),
+            r2: (r * r % p as u128) as u64,
+            root: 0,
+        };
+        let one = prime.to_mont(1);
+        let minus_one = p - one;
+        let c = (p - 1) >> NTT_LOG;
+        // a^c has order 2^NTT_LOG iff a is a non-residue
+        prime.root = (2..)
+            .map(|a| prime.pow(prime.to_mont(a), c))
+            .find(|&w| prime.pow(w, 1 << (NTT_LOG - 1)) == minus_one)
+            .unwrap();
+        prime
+    }
+
+    /// t.R^{-1} mod p, for t < p.2^64
+    #[inline(always)]
+    fn redc(&self, t: u128) -> u64 {
+        // m.p agrees with t on the low limb, so t - m.p = (hi(t) - hi(m.p)).R
+        let m = (t as u64).wrapping_mul(self.p_inv);
+        let (u, borrow) =
+            ((t >> 64) as u64).overflowing_sub(((m as u128 * self.p as u128) >> 64) as u64);
+        if borrow {
+            u.wrapping_add(self.p)
+        } else {
+            u
+        }
+    }
+
+    #[inline(always)]
+    fn mul(&self, a: u64, b: u64) -> u64 {
+        self.redc(a as u128 * b as u128)
+    }
+
+    #[inline(always)]
+    fn add(&self, a: u64, b: u64) -> u64 {
+        let s = a + b;
+        if s >= self.p {
+            s - self.p
+        } else {
+            s
+        }
+    }
+
+    #[inline(always)]
+    fn sub(&self, a: u64, b: u64) -> u64 {
+        if a >= b {
+            a - b
+        } else {
+            a + self.p - b
+        }
+    }
+
+    fn to_mont(&self, a: u64) -> u64 {
+        self.mul(a, self.r2)
+    }
+
+    /// a^e, a in Montgomery form
+    fn pow(&self, mut a: u64, mut e: u64) -> u64 {
+        let mut r = self.to_mont(1);
+        while e > 0 {
+            if e & 1 == 1 {
+                r = self.mul(r, a);
+            }
+            a = self.mul(a, a);
+            e >>= 1;
+        }
+        r
+    }
+
+    /// w^i for i < size/2, w a primitive size-th root of unity (or its
+    /// inverse), in Montgomery form
+    fn roots(&self, size: usize, inverse: bool) -> Vec<u64> {
+        let mut w = self.pow(self.root, (1u64 << NTT_LOG) / size as u64);
+        if inverse {
+            w = self.pow(w, size as u64 - 1);
+        }
+        let mut roots = Vec::with_capacity(size / 2);
+        let mut x = self.to_mont(1);
+        for _ in 0..size / 2 {
+            roots.push(x);
+            x = self.mul(x, w);
+        }
+        roots
+    }
+
+    /// Decimation in frequency: natural order in, bit-reversed order out
+    fn forward(&self, a: &mut [u64], roots: &[u64]) {
+        let mut half = a.len() / 2;
+        let mut stride = 1;
+        while half >= 1 {
+            for block in a.chunks_exact_mut(2 * half) {
+                let (lo, hi) = block.split_at_mut(half);
+                let twiddles = roots.iter().step_by(stride);
+                for ((x, y), &w) in lo.iter_mut().zip(hi.iter_mut()).zip(twiddles) {
+                    let (u, v) = (*x, *y);
+                    *x = self.add(u, v);
+                    *y = self.mul(self.sub(u, v), w);
+                }
+            }
+            half /= 2;
+            stride *= 2;
+        }
+    }
+
+    /// Decimation in time: bit-reversed order in, natural order out,
+    /// without the division by the size
+    fn inverse(&self, a: &mut [u64], inv_roots: &[u64]) {
+        let mut half = 1;
+        let mut stride = a.len() / 2;
+        while half < a.len() {
+            for block in a.chunks_exact_mut(2 * half) {
+                let (lo, hi) = block.split_at_mut(half);
+                let twiddles = inv_roots.iter().step_by(stride);
+                for ((x, y), &w) in lo.iter_mut().zip(hi.iter_mut()).zip(twiddles) {
+                    let (u, v) = (*x, self.mul(*y, w));
+                    *x = self.add(u, v);
+                    *y = self.sub(u, v);
+                }
+            }
+            half *= 2;
+            stride /= 2;
+        }
+    }
+}
+
+/// Polynomial arithmetic modulo n.
+///
+/// Long products are computed over the integers modulo enough NTT primes
+/// to determine them, then put back together by the CRT and reduced by n.
+/// The primes are found once, when the ring is created.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PolyRing {
+    n: Integer,
+    primes: Vec<NttPrime>,
+    /// 2^(64i) mod p_k for the limbs i of n, in Montgomery form, to
+    /// reduce coefficients by each prime
+    limb_powers: Vec<Vec<u64>>,
+    /// For each prime p_k: p_i mod p_k for i < k, and the inverse of
+    /// their product, in Montgomery form (Garner's algorithm)
+    garner: Vec<(Vec<u64>, u64)>,
+}
+
+/// The polynomials prod (X - r) over the leaves below each node, from the
+/// leaves (level 0) up to the root, ready for division by the root
+#[derive(Clone, Debug)]
+pub struct ProductTree {
+    levels: Vec<Vec<Vec<Integer>>>,
+    root: Divisor,
+}
+
+impl ProductTree {
+    /// prod (X - r) over all the leaves
+    pub fn root(&self) -> &[Integer] {
+        &self.root.f
+    }
+
+    pub fn divisor(&self) -> &Divisor {
+        &self.root
+    }
+
+    pub fn leaves(&self) -> usize {
+        self.levels[0].len()
+    }
+}
+
+/// A monic polynomial f, with 1/rev(f) to enough precision to reduce the
+/// products of two polynomials of degree < deg f
+#[derive(Clone, Debug)]
+pub struct Divisor {
+    f: Vec<Integer>,
+    inv: Vec<Integer>,
+}
+
+impl Divisor {
+    pub fn poly(&self) -> &[Integer] {
+        &self.f
+    }
+}
+
+impl PolyRing {
+    pub fn new(n: &Integer) -> PolyRing {
+        // products of two polynomials of length up to 2^NTT_LOG have
+        // coefficients below 2^NTT_LOG.n^2, every prime is > 2^61
+        let bits = 2 * n.significant_bits() + NTT_LOG + 1;
+        let count = bits.div_ceil(61) as usize;
+        let mut primes = vec![];
+        let mut c = (1u64 << (62 - NTT_LOG)) - 1;
+        while primes.len() < count {
+            let p = (c << NTT_LOG) + 1;
+            if Integer::from(p).is_probably_prime(30) != crate::arith::integer::IsPrime::No {
+                primes.push(NttPrime::new(p));
+            }
+            c -= 1;
+        }
+
+        let limbs = n.significant_digits::<u64>();
+        let limb_powers = primes
+            .iter()
+            .map(|prime| {
+                let base = prime.to_mont(((1u128 << 64) % prime.p as u128) as u64);
+                let mut x = prime.to_mont(1);
+                (0..limbs)
+                    .map(|_| {
+                        let r = x;
+                        x = prime.mul(x, base);
+                        r
+                    })
+                    .collect()
+            })
+            .collect();
+        let garner = (0..primes.len())
+            .map(|k| {
+                let prime = &primes[k];
+                let residues: Vec<u64> = primes[..k]
+                    .iter()
+                    .map(|q| prime.to_mont(q.p % prime.p))
+                    .collect();
+                let product = residues
+                    .iter()
+                    .fold(prime.to_mont(1), |acc, &r| prime.mul(acc, r));
+                // inverse by Fermat's little theorem
+                (residues, prime.pow(product, prime.p - 2))
+            })
+            .collect();
+        PolyRing {
+            n: Integer::from(n),
+            primes,
+            limb_powers,
+            garner,
+        }
+    }
+
+    pub fn modulus(&self) -> &Integer {
+        &self.n
+    }
+
+    /// a.b mod n
+    pub fn mul(&self, a: &[Integer], b: &[Integer]) -> Vec<Integer> {
+        if a.is_empty() || b.is_empty() {
+            return vec![];
+        }
+        if a.len().min(b.len()) >= NTT_THRESHOLD_PER_PRIME * self.ntt_primes(a, b) {
+            return self.mul_ntt(a, b);
+        }
+        let mut r = mul_z(a, b);
+        for c in r.iter_mut() {
+            *c = take_mod(c, &self.n);
+        }
+        r
+    }
+
+    /// a.b mod (n, X^len)
+    pub fn mul_low(&self, a: &[Integer], b: &[Integer], len: usize) -> Vec<Integer> {
+        let a = &a[..a.len().min(len)];
+        let b = &b[..b.len().min(len)];
+        let mut r = self.mul(a, b);
+        r.truncate(len);
+        r
+    }
+
+    /// Number of NTT primes whose product exceeds the coefficients of a.b
+    /// over Z, for coefficients in [0, n)
+    fn ntt_primes(&self, a: &[Integer], b: &[Integer]) -> usize {
+        let bits =
+            2 * self.n.significant_bits() + usize::BITS - a.len().min(b.len()).leading_zeros();
+        (bits as usize).div_ceil(61)
+    }
+
+    /// a.b over Z modulo NTT primes, put back together and reduced by n,
+    /// for coefficients in [0, n)
+    fn mul_ntt(&self, a: &[Integer], b: &[Integer]) -> Vec<Integer> {
+        let len = a.len() + b.len() - 1;
+        let size = len.next_power_of_two();
+        assert!(size.trailing_zeros() <= NTT_LOG, "product too long");
+        let count = self.ntt_primes(a, b);
+
+        let a_limbs: Vec<Vec<u64>> = a.iter().map(|c| c.to_digits(Order::Lsf)).collect();
+        let b_limbs: Vec<Vec<u64>> = b.iter().map(|c| c.to_digits(Order::Lsf)).collect();
+        let residues: Vec<Vec<u64>> = (0..count)
+            .map(|k| {
+                let prime = &self.primes[k];
+                let reduce = |limbs: &Vec<Vec<u64>>| {
+                    let mut r = vec![0; size];
+                    for (x, c) in r.iter_mut().zip(limbs) {
+                        *x = c
+                            .iter()
+                            .zip(&self.limb_powers[k])
+                            .fold(0, |acc, (&l, &pw)| prime.add(acc, prime.mul(l, pw)));
+                    }
+                    r
+                };
+                let (mut fa, mut fb) = (reduce(&a_limbs), reduce(&b_limbs));
+                let roots = prime.roots(size, false);
+                prime.forward(&mut fa, &roots);
+                prime.forward(&mut fb, &roots);
+                // the pointwise products carry a factor R^{-1}: scaling
+                // by R/size (R^2/size in Montgomery form) removes it
+                // along with the size
+                let inv_size = prime.pow(prime.to_mont(size as u64), prime.p - 2);
+                let scale = prime.to_mont(inv_size);
+                for (x, y) in fa.iter_mut().zip(&fb) {
+                    *x = prime.mul(*x, *y);
+                }
+                prime.inverse(&mut fa, &prime.roots(size, true));
+                fa.truncate(len);
+                for x in fa.iter_mut() {
+                    *x = prime.mul(*x, scale);
+                }
+                fa
+            })
+            .collect();
+
+        // Garner's algorithm: the mixed radix digits v_k of each
+        // coefficient, then sum v_k.p_0...p_{k-1}
+        let mut v = vec![0u64; count];
+        (0..len)
+            .map(|i| {
+                for k in 0..count {
+                    let prime = &self.primes[k];
+                    let (residues_k, inv) = &self.garner[k];
+                    // v_0 + v_1 p_0 + ... mod p_k, by Horner
+                    let mut s = 0;
+                    for j in (0..k).rev() {
+                        let v_j = if v[j] >= prime.p {
+                            v[j] - prime.p
+                        } else {
+                            v[j]
+                        };
+                        s = prime.add(prime.mul(s, residues_k[j]), v_j);
+                    }
+                    // residues[k][i] - s is plain, the inverse is in
+                    // Montgomery form
+                    v[k] = prime.mul(prime.sub(residues[k][i], s), *inv);
+                }
+                let mut x = Integer::from(v[count - 1]);
+                for k in (0..count - 1).rev() {
+                    x *= self.primes[k].p;
+                    x += v[k];
+                }
+                take_mod(&x, &self.n)
+            })
+            .collect()
+    }
+
+    /// g with f.g = 1 mod X^len, for f[0] = 1, by Newton's iteration
+    fn inverse_series(&self, f: &[Integer], len: usize) -> Vec<Integer> {
+        let mut g = vec![Integer::from(1)];
+        let mut prec = 1;
+        while prec < len {
+            prec = (2 * prec).min(len);
+            // g <- g + g(1 - fg) mod X^prec
+            let mut e = self.mul_low(f, &g, prec);
+            for c in e.iter_mut() {
+                if *c != 0 {
+                    *c = (&self.n - &*c).complete();
+                }
+            }
+            e[0] += 1;
+            if e[0] == self.n {
+                e[0] = Integer::new();
+            }
+            let ge = self.mul_low(&g, &e, prec);
+            g.resize(prec, Integer::new());
+            for (c, d) in g.iter_mut().zip(ge) {
+                *c += d;
+                if *c >= self.n {
+                    *c -= &self.n;
+                }
+            }
+        }
+        g
+    }
+
+    /// Prepares the division by a monic f
+    pub fn divisor(&self, f: Vec<Integer>) -> Divisor {
+        assert!(f.last() == Some(&Integer::from(1)), "divisor not monic");
+        let inv = if f.len() - 1 < DIVISION_THRESHOLD {
+            vec![]
+        } else {
+            let rev: Vec<Integer> = f.iter().rev().cloned().collect();
+            self.inverse_series(&rev, f.len() - 1)
+        };
+        Divisor { f, inv }
+    }
+
+    /// a mod f
+    pub fn rem(&self, a: &[Integer], f: &Divisor) -> Vec<Integer> {
+        let deg = f.f.len() - 1;
+        if a.len() <= deg {
+            return a.to_vec();
+        }
+        if deg < DIVISION_THRESHOLD {
+            return self.rem_schoolbook(a, &f.f);
+        }
+        // rev(q) = rev(a) / rev(f) mod X^(deg a - deg f + 1)
+        let q_len = a.len() - deg;
+        let rev_a: Vec<Integer> = a.iter().rev().take(q_len).cloned().collect();
+        let mut rev_q = if q_len <= f.inv.len() {
+            self.mul_low(&rev_a, &f.inv, q_len)
+        } else {
+            let rev_f: Vec<Integer> = f.f.iter().rev().cloned().collect();
+            self.mul_low(&rev_a, &self.inverse_series(&rev_f, q_len), q_len)
+        };
+        rev_q.resize(q_len, Integer::new());
+        rev_q.reverse();
+        // r = a - qf mod X^deg
+        let qf = self.mul_low(&rev_q, &f.f, deg);
+        let mut r: Vec<Integer> = a[..deg].to_vec();
+        for (c, d) in r.iter_mut().zip(qf) {
+            *c -= d;
+            if *c < 0 {
+                *c += &self.n;
+            }
+        }
+        r
+    }
+
+    /// a mod f by long division, f monic
+    fn rem_schoolbook(&self, a: &[Integer], f: &[Integer]) -> Vec<Integer> {
+        let deg = f.len() - 1;
+        let mut r = a.to_vec();
+        for i in (deg..r.len()).rev() {
+            // r -= c.X^(i - deg).f, for the leading coefficient c of r
+            let c = take_mod(&r[i], &self.n);
+            if c != 0 {
+                for (x, y) in r[i - deg..i].iter_mut().zip(f) {
+                    *x -= &c * y;
+                }
+            }
+        }
+        r.truncate(deg);
+        r.iter().map(|c| take_mod(c, &self.n)).collect()
+    }
+
+    /// a.b mod f
+    pub fn mul_rem(&self, a: &[Integer], b: &[Integer], f: &Divisor) -> Vec<Integer> {
+        self.rem(&self.mul(a, b), f)
+    }
+
+    /// prod (X - r) over the given roots
+    pub fn product(&self, roots: &[Integer]) -> Vec<Integer> {
+        let mut level = self.linear_factors(roots);
+        while level.len() > 1 {
+            level = self.next_level(&level);
+        }
+        level.pop().unwrap_or_else(|| vec![Integer::from(1)])
+    }
+
+    /// Product tree of the X - r over the given roots, at least one
+    pub fn product_tree(&self, roots: &[Integer]) -> ProductTree {
+        assert!(!roots.is_empty());
+        let mut levels = vec![self.linear_factors(roots)];
+        while levels[levels.len() - 1].len() > 1 {
+            levels.push(self.next_level(&levels[levels.len() - 1]));
+        }
+        let root = self.divisor(levels[levels.len() - 1][0].clone());
+        ProductTree { levels, root }
+    }
+
+    fn linear_factors(&self, roots: &[Integer]) -> Vec<Vec<Integer>> {
+        roots
+            .iter()
+            .map(|r| vec![take_mod(&(-r).complete(), &self.n), Integer::from(1)])
+            .collect()
+    }
+
+    fn next_level(&self, level: &[Vec<Integer>]) -> Vec<Vec<Integer>> {
+        level
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => self.mul(a, b),
+                [a] => a.clone(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    /// f(r) for each root r of the tree, by Bernstein's scaled remainder
+    /// tree: rather than h mod P, each node P gets the first deg P
+    /// coefficients s_k of h/P = sum s_k X^-k, computed from its parent
+    /// P.Q as the middle of (h/PQ).Q, which takes no division. At a leaf
+    /// X - r, s_1 = h(r).
+    pub fn evaluate(&self, f: &[Integer], tree: &ProductTree) -> Vec<Integer> {
+        let root = &tree.root;
+        let d = root.f.len() - 1;
+        let h = self.rem(f, root);
+        // h/F = X^-1 rev(h)/rev(F) in X^-1, h taken of degree d - 1
+        let rev_h: Vec<Integer> = (0..d)
+            .map(|i| h.get(d - 1 - i).cloned().unwrap_or_default())
+            .collect();
+        let mut scaled = vec![if root.inv.len() >= d {
+            self.mul_low(&rev_h, &root.inv, d)
+        } else {
+            let rev_f: Vec<Integer> = root.f.iter().rev().cloned().collect();
+            self.mul_low(&rev_h, &self.inverse_series(&rev_f, d), d)
+        }];
+        let top = tree.levels.len() - 1;
+        for level in tree.levels[..top].iter().rev() {
+            scaled = level
+                .iter()
+                .enumerate()
+                .map(|(i, node)| {
+                    let parent = &scaled[i / 2];
+                    let sibling = match level.get(i ^ 1) {
+                        Some(sibling) => sibling,
+                        // carried up alone, the parent is the node
+                        None => return parent.clone(),
+                    };
+                    // s'_t = sum Q_i s_(t+i), t = 1..deg P
+                    let deg_q = sibling.len() - 1;
+                    let rev_q: Vec<Integer> = sibling.iter().rev().cloned().collect();
+                    let product = self.mul(parent, &rev_q);
+                    (deg_q..deg_q + node.len() - 1)
+                        .map(|k| product.get(k).cloned().unwrap_or_default())
+                        .collect()
+                })
+                .collect();
+        }
+        scaled
+            .into_iter()
+            .map(|s| s.into_iter().next().unwrap_or_default())
+            .collect()
+    }
+}
+
+/// a + b over Z
+fn add_z(a: &[Integer], b: &[Integer]) -> Vec<Integer> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut r = long.to_vec();
+    for (c, d) in r.iter_mut().zip(short) {
+        *c += d;
+    }
+    r
+}
+
+/// r += a.X^shift over Z, dropping what does not fit in r
+fn add_shifted(r: &mut [Integer], a: &[Integer], shift: usize) {
+    for (c, d) in r.iter_mut().skip(shift).zip(a) {
+        *c += d;
+    }
+}
+
+/// r -= a over Z, r being long enough
+fn sub_assign_z(r: &mut [Integer], a: &[Integer]) {
+    for (c, d) in r.iter_mut().zip(a) {
+        *c -= d;
+    }
+}
+
+/// a.b over Z
+fn mul_z(a: &[Integer], b: &[Integer]) -> Vec<Integer> {
+    if a.is_empty() || b.is_empty() {
+        return vec![];
+    }
+    let min = a.len().min(b.len());
+    if min < KARATSUBA_THRESHOLD {
+        schoolbook(a, b)
+    } else if min < TOOM3_THRESHOLD {
+        karatsuba(a, b)
+    } else {
+        toom3(a, b)
+    }
+}
+
+fn schoolbook(a: &[Integer], b: &[Integer]) -> Vec<Integer> {
+    let mut r = vec![Integer::new(); a.len() + b.len() - 1];
+    for (i, x) in a.iter().enumerate() {
+        for (j, y) in b.iter().enumerate() {
+            r[i + j] += x * y;
+        }
+    }
+    r
+}
+
+/// Splits a into chunks of h coefficients, the last ones possibly empty
+fn split<const K: usize>(a: &[Integer], h: usize) -> [&[Integer]; K] {
+    let mut parts = [&a[..0]; K];
+    for (i, part) in parts.iter_mut().enumerate() {
+        let lo = (i * h).min(a.len());
+        let hi = if i == K - 1 {
+            a.len()
+        } else {
+            ((i + 1) * h).min(a.len())
+        };
+        *part = &a[lo..hi];
+    }
+    parts
+}
+
+fn karatsuba(a: &[Integer], b: &[Integer]) -> Vec<Integer> {
+    let h = a.len().max(b.len()).div_ceil(2);
+    let [a0, a1] = split::<2>(a, h);
+    let [b0, b1] = split::<2>(b, h);
+    let z0 = mul_z(a0, b0);
+    let z2 = mul_z(a1, b1);
+    // z1 = (a0 + a1)(b0 + b1) - z0 - z2
+    let mut z1 = mul_z(&add_z(a0, a1), &add_z(b0, b1));
+    sub_assign_z(&mut z1, &z0);
+    sub_assign_z(&mut z1, &z2);
+
+    let mut r = vec![Integer::new(); a.len() + b.len() - 1];
+    add_shifted(&mut r, &z0, 0);
+    add_shifted(&mut r, &z1, h);
+    add_shifted(&mut r, &z2, 2 * h);
+    r
+}
+
+/// Toom-Cook 3-way, evaluating at 0, 1, -1, -2 and infinity and
+/// interpolating with Bodrato's sequence
+fn toom3(a: &[Integer], b: &[Integer]) -> Vec<Integer> {
+    let h = a.len().max(b.len()).div_ceil(3);
+    // p(0), p(1), p(-1), p(-2), p(inf) for p = p0 + p1 X + p2 X^2
+    let evaluate = |p: &[Integer]| {
+        let [p0, p1, p2] = split::<3>(p, h);
+        let p02 = add_z(p0, p2);
+        let at_one = add_z(&p02, p1);
+        let mut at_minus_one = p02;
+        sub_assign_z(&mut at_minus_one, p1);
+        // p(-2) = 2(p(-1) + p2) - p0
+        let mut at_minus_two = add_z(&at_minus_one, p2);
+        for c in at_minus_two.iter_mut() {
+            *c <<= 1;
+        }
+        sub_assign_z(&mut at_minus_two, p0);
+        [p0.to_vec(), at_one, at_minus_one, at_minus_two, p2.to_vec()]
+    };
+    let [a0, a1, am1, am2, ainf] = evaluate(a);
+    let [b0, b1, bm1, bm2, binf] = evaluate(b);
+    let r0 = mul_z(&a0, &b0);
+    let mut r1 = mul_z(&a1, &b1);
+    let rm1 = mul_z(&am1, &bm1);
+    let rm2 = mul_z(&am2, &bm2);
+    let rinf = mul_z(&ainf, &binf);
+
+    let len = 2 * h + 1;
+    let pad = |mut v: Vec<Integer>| {
+        v.resize(len.max(v.len()), Integer::new());
+        v
+    };
+    let (mut r1, rm1) = (pad(std::mem::take(&mut r1)), pad(rm1));
+    // r3 = (r(-2) - r(1)) / 3
+    let mut r3 = pad(rm2);
+    sub_assign_z(&mut r3, &r1);
+    for c in r3.iter_mut() {
+        c.div_exact_u_mut(3);
+    }
+    // r1 = (r(1) - r(-1)) / 2
+    sub_assign_z(&mut r1, &rm1);
+    for c in r1.iter_mut() {
+        c.div_exact_u_mut(2);
+    }
+    // r2 = r(-1) - r(0)
+    let mut r2 = rm1;
+    sub_assign_z(&mut r2, &r0);
+    // r3 = (r2 - r3) / 2 + 2 r(inf)
+    for (c, d) in r3.iter_mut().zip(&r2) {
+        *c = (d - &*c).complete();
+        c.div_exact_u_mut(2);
+    }
+    for (c, d) in r3.iter_mut().zip(&rinf) {
+        *c += (d << 1u32).complete();
+    }
+    // r2 = r2 + r1 - r(inf)
+    for (c, d) in r2.iter_mut().zip(&r1) {
+        *c += d;
+    }
+    sub_assign_z(&mut r2, &rinf);
+    // r1 = r1 - r3
+    sub_assign_z(&mut r1, &r3);
+
+    let mut r = vec![Integer::new(); a.len() + b.len() - 1 + 4 * h];
+    add_shifted(&mut r, &r0, 0);
+    add_shifted(&mut r, &r1, h);
+    add_shifted(&mut r, &r2, 2 * h);
+    add_shifted(&mut r, &r3, 3 * h);
+    add_shifted(&mut r, &rinf, 4 * h);
+    r.truncate(a.len() + b.len() - 1);
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::integer::RandState;
+
+    fn random_poly(rand: &mut RandState, n: &Integer, len: usize) -> Vec<Integer> {
+        (0..len)
+            .map(|_| n.random_below_ref(rand).complete())
+            .collect()
+    }
+
+    fn reduce(a: Vec<Integer>, n: &Integer) -> Vec<Integer> {
+        a.iter().map(|c| take_mod(c, n)).collect()
+    }
+
+    #[test]
+    fn poly_mul_tests() {
+        let mut rand = RandState::new();
+        for bits in [20, 200, 1000] {
+            let n = Integer::random_bits(bits, &mut rand).complete() | 1;
+            let ring = PolyRing::new(&n);
+            for (la, lb) in [
+                (1, 1),
+                (5, 30),
+                (40, 40),
+                (50, 70),
+                (100, 100),
+                (130, 200),
+                (300, 20),
+            ] {
+                let a = random_poly(&mut rand, &n, la);
+                let b = random_poly(&mut rand, &n, lb);
+                let expected = reduce(schoolbook(&a, &b), &n);
+                assert_eq!(reduce(karatsuba(&a, &b), &n), expected);
+                assert_eq!(reduce(toom3(&a, &b), &n), expected);
+                assert_eq!(ring.mul(&a, &b), expected);
+                if la.min(lb) > 1 {
+                    assert_eq!(ring.mul_ntt(&a, &b), expected, "{} {}", la, lb);
+                }
+            }
+            // the largest coefficients the NTT must get right
+            let top = vec![(&n - 1u32).complete(); 150];
+            assert_eq!(ring.mul_ntt(&top, &top), reduce(schoolbook(&top, &top), &n));
+        }
+    }
+
+    #[test]
+    fn poly_rem_tests() {
+        let mut rand = RandState::new();
+        let n = Integer::random_bits(300, &mut rand).complete() | 1;
+        let ring = PolyRing::new(&n);
+        for (la, deg) in [(10, 3), (200, 100), (300, 120), (50, 60)] {
+            let a = random_poly(&mut rand, &n, la);
+            let mut f = random_poly(&mut rand, &n, deg);
+            f.push(Integer::from(1));
+            let d = ring.divisor(f.clone());
+            let r = ring.rem(&a, &d);
+            assert!(r.len() <= deg);
+            // a - r is a multiple of f: it vanishes at the roots of f,
+            // checked on a product of linear factors instead
+            let roots = random_poly(&mut rand, &n, deg);
+            let g = ring.product(&roots);
+            let dg = ring.divisor(g.clone());
+            let r = ring.rem(&a, &dg);
+            let tree = ring.product_tree(&roots);
+            assert_eq!(tree.root(), &g[..]);
+            let values = ring.evaluate(&a, &tree);
+            for (x, v) in roots.iter().zip(&values) {
+                let horner = |p: &[Integer]| {
+                    p.iter()
+                        .rev()
+                        .fold(Integer::new(), |acc, c| take_mod(&(acc * x + c), &n))
+                };
+                assert_eq!(horner(&a), *v);
+                assert_eq!(horner(&r), *v);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+/// Multiplications in a differential addition and in a doubling
+pub(crate) const ADD: u64 = 6;
+pub(crate) const DUP: u64 = 5;
+
+/// Ratios r/k tried for the first step of PRAC, close to the golden ratio
+/// (values from GMP-ECM)
+const RATIOS: [f64; 10] = [
+    0.6180339887498949,
+    0.7236067977499789,
+    0.5801787282954641,
+    0.6328398060887063,
+    0.612429949509495,
+    0.6201819808074157,
+    0.6172146165344039,
+    0.619085130300099,
+    0.6207009023910789,
+    0.6179430900989559,
+];
+
+/// One step of a PRAC chain: either swapping the points A and B, or the
+/// rule of the given number in Table 4 of Montgomery's "Evaluating
+/// recurrences of form X_{m+n} = f(X_m, X_n, X_{m-n}) via Lucas chains".
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PracStep {
+    Swap,
+    Rule(u8),
+}
+
+/// Lucas chain for k computed by Montgomery's PRAC algorithm: a sequence
+/// of differential additions and doublings computing [k]P from P, that
+/// for most k is shorter than the Montgomery ladder.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LucasChain {
+    k: u64,
+    steps: Vec<PracStep>,
+    cost: u64,
+}
+
+impl LucasChain {
+    /// Best chain among the ratios of `RATIOS`, for an odd k >= 3
+    pub fn new(k: u64) -> LucasChain {
+        assert!(
+            k >= 3 && !k.is_multiple_of(2),
+            "PRAC needs an odd k >= 3, got {}",
+            k
+        );
+        RATIOS
+            .iter()
+            .filter_map(|&v| LucasChain::with_ratio(k, v))
+            .min_by_key(|chain| chain.cost)
+            .expect("the golden ratio always gives a chain")
+    }
+
+    /// Runs PRAC on the scalars only, starting from r = k.v.
+    /// Returns None if that r does not lead to a chain.
+    fn with_ratio(k: u64, v: f64) -> Option<LucasChain> {
+        let r = (k as f64 * v + 0.5) as u64;
+        if r >= k || 2 * r <= k {
+            return None;
+        }
+        let mut steps = vec![];
+        // initial doubling and final addition
+        let mut cost = DUP + ADD;
+        let (mut d, mut e) = (k - r, 2 * r - k);
+        while d != e {
+            if d < e {
+                std::mem::swap(&mut d, &mut e);
+                steps.push(PracStep::Swap);
+            }
+            let (rule, c) = if d - e <= e / 4 && (d + e) % 3 == 0 {
+                d = (2 * d - e) / 3;
+                e = (e - d) / 2;
+                (1, 3 * ADD)
+            } else if d - e <= e / 4 && (d - e) % 6 == 0 {
+                d = (d - e) / 2;
+                (2, ADD + DUP)
+            } else if d.div_ceil(4) <= e {
+                d -= e;
+                (3, ADD)
+            } else if (d + e) % 2 == 0 {
+                d = (d - e) / 2;
+                (4, ADD + DUP)
+            } else if d % 2 == 0 {
+                // now d + e is odd
+                d /= 2;
+                (5, ADD + DUP)
+            } else if d % 3 == 0 {
+                // now d is odd and e even
+                d = d / 3 - e;
+                (6, 3 * ADD + DUP)
+            } else if (d + e) % 3 == 0 {
+                d = (d - 2 * e) / 3;
+                (7, 3 * ADD + DUP)
+            } else if (d - e) % 3 == 0 {
+                d = (d - e) / 3;
+                (8, 3 * ADD + DUP)
+            } else {
+                e /= 2;
+                (9, ADD + DUP)
+            };
+            steps.push(PracStep::Rule(rule));
+            cost += c;
+            if e == 0 {
+                return None;
+            }
+        }
+        // the chain only computes [k]P if gcd(k - r, 2r - k) = 1
+        if d != 1 {
+            return None;
+        }
+        Some(LucasChain { k, steps, cost })
+    }
+
+    pub fn k(&self) -> u64 {
+        self.k
+    }
+
+    pub fn steps(&self) -> &[PracStep] {
+        &self.steps
+    }
+
+    /// Number of multiplications the chain takes
+    pub fn cost(&self) -> u64 {
+        self.cost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::integer::Integer;
+    use crate::arith::montgomery_point::MontgomeryCurve;
+    use crate::arith::ring::DefaultRing;
+
+    #[test]
+    fn lucas_chain_tests() {
+        MontgomeryCurve::<DefaultRing>::with_a24(
+            Integer::from(123456789),
+            Integer::from(1000000007),
+            |curve| {
+                let p = curve.point(&Integer::from(1234), &Integer::from(1));
+                let mut scratch = [(); 5].map(|_| curve.zero_point());
+                for k in (3..2000).step_by(2).chain([1000003, 4294967311]) {
+                    let chain = LucasChain::new(k);
+                    let mut q = p.clone();
+                    curve.lucas_chain_assign(&mut q, &chain, &mut scratch);
+                    assert!(
+                        q.equals(&curve.montgomery_ladder(&p, &Integer::from(k))),
+                        "k={}",
+                        k
+                    );
+                }
+            },
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use crate::arith::integer::Integer;
+use crate::arith::modular_arithmetic::*;
+use std::fmt::Debug;
+
+/// The ring Z/nZ the curve arithmetic is carried out in.
+///
+/// A backend picks its own representation of residues; integers only go
+/// in and out at curve setup and when taking gcds with n. The `*_into`
+/// and `*_assign` operations write into an existing residue and should
+/// not allocate.
+pub trait ModularRing: Clone + PartialEq + Debug + Sized {
+    type Elem: Clone + PartialEq + Debug;
+
+    /// Returns None if this backend cannot work modulo n
+    fn new(n: &Integer) -> Option<Self>;
+
+    fn modulus(&self) -> &Integer;
+
+    /// Converts a (reduced mod n) into the ring
+    fn to_residue(&self, a: &Integer) -> Self::Elem;
+
+    /// Converts a back into an integer of [0, n)
+    fn to_integer(&self, a: &Self::Elem) -> Integer;
+
+    fn zero(&self) -> Self::Elem;
+
+    fn one(&self) -> Self::Elem;
+
+    fn is_zero(&self, a: &Self::Elem) -> bool;
+
+    /// r = a + b
+    fn add_into(&self, r: &mut Self::Elem, a: &Self::Elem, b: &Self::Elem);
+
+    /// r = r + b
+    fn add_assign(&self, r: &mut Self::Elem, b: &Self::Elem);
+
+    /// r = a - b
+    fn sub_into(&self, r: &mut Self::Elem, a: &Self::Elem, b: &Self::Elem);
+
+    /// r = r - b
+    fn sub_assign(&self, r: &mut Self::Elem, b: &Self::Elem);
+
+    /// r = a * b
+    fn mul_into(&self, r: &mut Self::Elem, a: &Self::Elem, b: &Self::Elem);
+
+    /// r = r * b
+    fn mul_assign(&self, r: &mut Self::Elem, b: &Self::Elem);
+
+    /// r = a^2
+    fn square_into(&self, r: &mut Self::Elem, a: &Self::Elem);
+
+    /// r = r^2
+    fn square_assign(&self, r: &mut Self::Elem);
+
+    /// Inverse of a mod n, if it exists
+    fn invert(&self, a: &Self::Elem) -> Option<Self::Elem> {
+        invert_mod(&self.to_integer(a), self.modulus()).map(|inv| self.to_residue(&inv))
+    }
+
+    /// Replaces each of values by its inverse, with a single inversion
+    /// (Montgomery's trick). If one of them is not invertible, leaves them
+    /// unchanged and returns the gcd of their product with n.
+    fn batch_invert(&self, values: &mut [Self::Elem]) -> Result<(), Integer> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        // prefix[i] = values[0] * ... * values[i]
+        let mut prefix = Vec::with_capacity(values.len());
+        let mut acc = self.one();
+        for v in values.iter() {
+            self.mul_assign(&mut acc, v);
+            prefix.push(acc.clone());
+        }
+        let mut inv = match self.invert(&acc) {
+            Some(inv) => inv,
+            None => return Err(self.gcd(&acc)),
+        };
+        for i in (1..values.len()).rev() {
+            // inv = (values[0] * ... * values[i])^-1 here
+            let value_inv = self.mul(&inv, &prefix[i - 1]);
+            self.mul_assign(&mut inv, &values[i]);
+            values[i] = value_inv;
+        }
+        values[0] = inv;
+        Ok(())
+    }
+
+    /// gcd(a, n)
+    fn gcd(&self, a: &Self::Elem) -> Integer {
+        self.to_integer(a).gcd(self.modulus())
+    }
+
+    fn add(&self, a: &Self::Elem, b: &Self::Elem) -> Self::Elem {
+        let mut r = self.zero();
+        self.add_into(&mut r, a, b);
+        r
+    }
+
+    fn sub(&self, a: &Self::Elem, b: &Self::Elem) -> Self::Elem {
+        let mut r = self.zero();
+        self.sub_into(&mut r, a, b);
+        r
+    }
+
+    fn mul(&self, a: &Self::Elem, b: &Self::Elem) -> Self::Elem {
+        let mut r = self.zero();
+        self.mul_into(&mut r, a, b);
+        r
+    }
+
+    fn square(&self, a: &Self::Elem) -> Self::Elem {
+        let mut r = self.zero();
+        self.square_into(&mut r, a);
+        r
+    }
+}
+
+/// Backend used by the ECM entry points
+#[cfg(feature = "gmp")]
+pub type DefaultRing = crate::arith::gmp_ring::MontgomeryContext;
+#[cfg(not(feature = "gmp"))]
+pub type DefaultRing = IntegerRing;
+
+/// Plain `Integer`s reduced by a division after each operation,
+/// i.e. the functions of `modular_arithmetic` behind the `ModularRing`
+/// interface. Slow, but works for any modulus and needs no unsafe code.
+#[derive(Clone, PartialEq, Debug)]
+pub struct IntegerRing {
+    n: Integer,
+}
+
+impl ModularRing for IntegerRing {
+    type Elem = Integer;
+
+    fn new(n: &Integer) -> Option<IntegerRing> {
+        if *n < 2 {
+            return None;
+        }
+        Some(IntegerRing {
+            n: Integer::from(n),
+        })
+    }
+
+    fn modulus(&self) -> &Integer {
+        &self.n
+    }
+
+    fn to_residue(&self, a: &Integer) -> Integer {
+        take_mod(a, &self.n)
+    }
+
+    fn to_integer(&self, a: &Integer) -> Integer {
+        Integer::from(a)
+    }
+
+    fn zero(&self) -> Integer {
+        Integer::new()
+    }
+
+    fn one(&self) -> Integer {
+        Integer::from(1)
+    }
+
+    fn is_zero(&self, a: &Integer) -> bool {
+        *a == 0
+    }
+
+    fn add_into(&self, r: &mut Integer, a: &Integer, b: &Integer) {
+        *r = add_mod(a, b, &self.n);
+    }
+
+    fn add_assign(&self, r: &mut Integer, b: &Integer) {
+        *r = add_mod(r, b, &self.n);
+    }
+
+    fn sub_into(&self, r: &mut Integer, a: &Integer, b: &Integer) {
+        *r = subtract_mod(a, b, &self.n);
+    }
+
+    fn sub_assign(&self, r: &mut Integer, b: &Integer) {
+        *r = subtract_mod(r, b, &self.n);
+    }
+
+    fn mul_into(&self, r: &mut Integer, a: &Integer, b: &Integer) {
+        *r = multiply_mod(a, b, &self.n);
+    }
+
+    fn mul_assign(&self, r: &mut Integer, b: &Integer) {
+        *r = multiply_mod(r, b, &self.n);
+    }
+
+    fn square_into(&self, r: &mut Integer, a: &Integer) {
+        *r = multiply_mod(a, a, &self.n);
+    }
+
+    fn square_assign(&self, r: &mut Integer) {
+        *r = multiply_mod(r, r, &self.n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::fixed_ring::FixedRing;
+    #[cfg(feature = "gmp")]
+    use crate::arith::gmp_ring::MontgomeryContext;
+    use crate::arith::integer::Complete;
+
+    fn ring_tests<R: ModularRing>() {
+        // 2^521 - 1 is prime
+        let n = (Integer::from(1) << 521) - Integer::from(1);
+        let ring = R::new(&n).unwrap();
+        let a = Integer::from(123456789) * Integer::from(987654321) * 1000003;
+        let b = (&n - 5u32).complete();
+        let (ra, rb) = (ring.to_residue(&a), ring.to_residue(&b));
+        assert_eq!(ring.to_integer(&ra), a);
+        assert_eq!(
+            ring.to_integer(&ring.mul(&ra, &rb)),
+            multiply_mod(&a, &b, &n)
+        );
+        assert_eq!(ring.to_integer(&ring.square(&rb)), Integer::from(25));
+        assert_eq!(ring.to_integer(&ring.add(&ra, &rb)), add_mod(&a, &b, &n));
+        assert_eq!(
+            ring.to_integer(&ring.sub(&ra, &rb)),
+            subtract_mod(&a, &b, &n)
+        );
+        assert_eq!(ring.to_integer(&ring.one()), Integer::from(1));
+        assert!(ring.is_zero(&ring.to_residue(&n)));
+        let inv = ring.invert(&ra).unwrap();
+        assert_eq!(ring.mul(&inv, &ra), ring.one());
+
+        let mut r = ra.clone();
+        ring.mul_assign(&mut r, &rb);
+        assert_eq!(r, ring.mul(&ra, &rb));
+        ring.square_assign(&mut r);
+        assert_eq!(r, ring.square(&ring.mul(&ra, &rb)));
+        ring.add_assign(&mut r, &rb);
+        ring.sub_assign(&mut r, &ra);
+        assert_eq!(
+            r,
+            ring.sub(&ring.add(&ring.square(&ring.mul(&ra, &rb)), &rb), &ra)
+        );
+
+        let m = Integer::from(1000003) * Integer::from(999983);
+        let ring = R::new(&m).unwrap();
+        let c = ring.to_residue(&Integer::from(2 * 999983));
+        assert_eq!(ring.invert(&c), None);
+        assert_eq!(ring.gcd(&c), 999983);
+
+        let mut values: Vec<_> = (1..20u32)
+            .map(|i| ring.to_residue(&Integer::from(i * i + 7)))
+            .collect();
+        let expected: Vec<_> = values.iter().map(|v| ring.invert(v).unwrap()).collect();
+        ring.batch_invert(&mut values).unwrap();
+        assert_eq!(values, expected);
+        values.push(c);
+        assert_eq!(ring.batch_invert(&mut values), Err(Integer::from(999983)));
+    }
+
+    #[test]
+    fn integer_ring_tests() {
+        ring_tests::<IntegerRing>();
+    }
+
+    #[test]
+    fn fixed_ring_tests() {
+        // 521 bits fit in 9 limbs
+        ring_tests::<FixedRing<9>>();
+        ring_tests::<FixedRing<16>>();
+    }
+
+    #[cfg(feature = "gmp")]
+    #[test]
+    fn montgomery_context_ring_tests() {
+        ring_tests::<MontgomeryContext>();
+    }
+}
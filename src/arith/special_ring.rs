@@ -0,0 +1,296 @@
+use crate::arith::integer::{Assign, Complete, Integer};
+use crate::arith::modular_arithmetic::take_mod;
+use crate::arith::ring::ModularRing;
+use std::cell::RefCell;
+use std::fmt;
+
+/// Largest base tried when looking for a special form
+const MAX_BASE: u32 = 12;
+
+/// A number of the form base^exponent + 1 or base^exponent - 1
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SpecialForm {
+    pub base: u32,
+    pub exponent: u32,
+    pub plus_one: bool,
+}
+
+impl SpecialForm {
+    pub fn value(&self) -> Integer {
+        let bk = Integer::u_pow_u(self.base, self.exponent).complete();
+        if self.plus_one {
+            bk + 1
+        } else {
+            bk - 1
+        }
+    }
+
+    /// Looks for the smallest b^k +- 1 that n divides, b <= 12, among
+    /// those at most twice as long as n: working modulo a longer number
+    /// would cost more than the reduction saves.
+    pub fn detect(n: &Integer) -> Option<SpecialForm> {
+        if *n < 3 {
+            return None;
+        }
+        let max_bits = 2.0 * n.significant_bits() as f64;
+        let minus_one = (n - 1u32).complete();
+        for base in 2..=MAX_BASE {
+            let max_exponent = (max_bits / (base as f64).log2()) as u32;
+            // t = base^k mod n
+            let mut t = Integer::from(1);
+            for exponent in 1..=max_exponent {
+                t *= base;
+                if t >= *n {
+                    t %= n;
+                }
+                if t == 1 || t == minus_one {
+                    return Some(SpecialForm {
+                        base,
+                        exponent,
+                        plus_one: t != 1,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for SpecialForm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.plus_one { '+' } else { '-' };
+        write!(f, "{}^{}{}1", self.base, self.exponent, sign)
+    }
+}
+
+thread_local! {
+    /// Per-thread quotient and remainder of the splits at b^k
+    static SPLIT: RefCell<(Integer, Integer)> = const { RefCell::new((Integer::new(), Integer::new())) };
+}
+
+/// Arithmetic mod a cofactor n of a special form m = b^k +- 1.
+///
+/// Residues are kept mod m rather than mod n: writing x = hi.b^k + lo,
+/// x = lo -+ hi mod m, which for b a power of two only takes shifts and
+/// additions. Reduction by n itself happens only when converting back to
+/// integers and taking gcds. Residues are therefore not unique, two of
+/// them are equal as elements of Z/nZ iff `to_integer` agrees on them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SpecialFormRing {
+    n: Integer,
+    form: SpecialForm,
+    /// m = b^k +- 1
+    m: Integer,
+    /// b^k
+    bk: Integer,
+    /// log2(b^k), when b is a power of two
+    shift: Option<u32>,
+}
+
+impl SpecialFormRing {
+    pub fn form(&self) -> SpecialForm {
+        self.form
+    }
+
+    /// Splits r into r div b^k (written to hi) and r mod b^k, r >= 0
+    fn split(&self, r: &mut Integer, hi: &mut Integer, lo: &mut Integer) {
+        match self.shift {
+            Some(s) => {
+                hi.assign(&*r >> s);
+                r.keep_bits_mut(s);
+            }
+            None => {
+                (&mut *hi, &mut *lo).assign(r.div_rem_floor_ref(&self.bk));
+                std::mem::swap(r, lo);
+            }
+        }
+    }
+
+    /// Brings 0 <= r < m^2 back to [0, m)
+    fn reduce(&self, r: &mut Integer) {
+        SPLIT.with(|split| {
+            let (hi, lo) = &mut *split.borrow_mut();
+            // for b^k - 1, b^k itself is left to the last step
+            let limit = if self.form.plus_one {
+                &self.m
+            } else {
+                &self.bk
+            };
+            while *r >= *limit {
+                self.split(r, hi, lo);
+                if self.form.plus_one {
+                    *r -= &*hi;
+                    while *r < 0 {
+                        *r += &self.m;
+                    }
+                } else {
+                    *r += &*hi;
+                }
+            }
+            if *r == self.m {
+                r.assign(0);
+            }
+        })
+    }
+}
+
+impl ModularRing for SpecialFormRing {
+    type Elem = Integer;
+
+    /// Returns None if n divides no b^k +- 1 found by `SpecialForm::detect`
+    fn new(n: &Integer) -> Option<SpecialFormRing> {
+        let form = SpecialForm::detect(n)?;
+        let shift = if form.base.is_power_of_two() {
+            Some(form.base.trailing_zeros() * form.exponent)
+        } else {
+            None
+        };
+        Some(SpecialFormRing {
+            n: Integer::from(n),
+            form,
+            m: form.value(),
+            bk: Integer::u_pow_u(form.base, form.exponent).complete(),
+            shift,
+        })
+    }
+
+    fn modulus(&self) -> &Integer {
+        &self.n
+    }
+
+    fn to_residue(&self, a: &Integer) -> Integer {
+        take_mod(a, &self.n)
+    }
+
+    /// Reduces a by the cofactor n
+    fn to_integer(&self, a: &Integer) -> Integer {
+        take_mod(a, &self.n)
+    }
+
+    fn zero(&self) -> Integer {
+        Integer::new()
+    }
+
+    fn one(&self) -> Integer {
+        Integer::from(1)
+    }
+
+    fn is_zero(&self, a: &Integer) -> bool {
+        a.is_divisible(&self.n)
+    }
+
+    fn add_into(&self, r: &mut Integer, a: &Integer, b: &Integer) {
+        r.assign(a + b);
+        if *r >= self.m {
+            *r -= &self.m;
+        }
+    }
+
+    fn add_assign(&self, r: &mut Integer, b: &Integer) {
+        *r += b;
+        if *r >= self.m {
+            *r -= &self.m;
+        }
+    }
+
+    fn sub_into(&self, r: &mut Integer, a: &Integer, b: &Integer) {
+        r.assign(a - b);
+        if *r < 0 {
+            *r += &self.m;
+        }
+    }
+
+    fn sub_assign(&self, r: &mut Integer, b: &Integer) {
+        *r -= b;
+        if *r < 0 {
+            *r += &self.m;
+        }
+    }
+
+    fn mul_into(&self, r: &mut Integer, a: &Integer, b: &Integer) {
+        r.assign(a * b);
+        self.reduce(r);
+    }
+
+    fn mul_assign(&self, r: &mut Integer, b: &Integer) {
+        *r *= b;
+        self.reduce(r);
+    }
+
+    fn square_into(&self, r: &mut Integer, a: &Integer) {
+        r.assign(a.square_ref());
+        self.reduce(r);
+    }
+
+    fn square_assign(&self, r: &mut Integer) {
+        r.square_mut();
+        self.reduce(r);
+    }
+
+    /// gcd(a, n), which n dividing m makes independent of the representative
+    fn gcd(&self, a: &Integer) -> Integer {
+        a.gcd_ref(&self.n).complete()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::modular_arithmetic::multiply_mod;
+
+    #[test]
+    fn special_form_detect() {
+        // F_7 = 2^128 + 1, and its cofactor by 59649589127497217
+        let f7 = (Integer::from(1) << 128u32) + 1u32;
+        let form = SpecialForm::detect(&f7).unwrap();
+        assert_eq!(form.to_string(), "2^128+1");
+        let c = (&f7 / 59649589127497217u64).complete();
+        assert_eq!(SpecialForm::detect(&c), Some(form));
+        // 2^67 - 1 = 193707721 * 761838257287
+        let m67 = Integer::from(761838257287u64);
+        assert_eq!(SpecialForm::detect(&m67).unwrap().to_string(), "2^67-1");
+        // 10^11 + 1 = 11^2 * 23 * 4093 * 8779
+        let c = Integer::from(4093 * 8779);
+        let form = SpecialForm::detect(&c).unwrap();
+        assert!(form.value().is_divisible(&c));
+        assert_eq!(
+            SpecialForm::detect(&Integer::from(1000003u64 * 999983)),
+            None
+        );
+    }
+
+    #[test]
+    fn special_form_ring_tests() {
+        let f7 = (Integer::from(1) << 128u32) + 1u32;
+        let m67 = Integer::from(761838257287u64);
+        let gf = Integer::u_pow_u(10, 16).complete() + 1u32;
+        // 10 is not a power of two, the last one splits by division
+        for (n, form) in [
+            ((&f7 / 59649589127497217u64).complete(), "2^128+1"),
+            (m67, "2^67-1"),
+            ((&gf / 353u32).complete(), "10^16+1"),
+        ] {
+            let ring = SpecialFormRing::new(&n).unwrap();
+            assert_eq!(ring.form().to_string(), form);
+            let a = (&n - 12345u32).complete();
+            let b = (&n / 3u32).complete();
+            let (ra, rb) = (ring.to_residue(&a), ring.to_residue(&b));
+            let mut p = ring.mul(&ra, &rb);
+            assert_eq!(ring.to_integer(&p), multiply_mod(&a, &b, &n));
+            for _ in 0..100 {
+                ring.square_assign(&mut p);
+                assert!(p >= 0 && p < ring.m);
+            }
+            let mut q = multiply_mod(&a, &b, &n);
+            for _ in 0..100 {
+                q = multiply_mod(&q, &q, &n);
+            }
+            assert_eq!(ring.to_integer(&p), q);
+            assert_eq!(
+                ring.to_integer(&ring.sub(&rb, &ra)),
+                (&b - &a).complete() + &n
+            );
+            assert!(ring.is_zero(&ring.add(&ra, &ring.to_residue(&Integer::from(12345)))));
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use crate::arith::integer::Integer;
+use crate::arith::montgomery_point::{FullCurve, FullPoint};
+use crate::arith::ring::ModularRing;
+
+/// Short Weierstrass curve y^2 = x^3 + ax + b mod n, on which points are
+/// kept in affine coordinates as in Lenstra's original ECM: every
+/// addition takes an inversion, and an inversion that fails reveals a
+/// factor of n.
+///
+/// The point at infinity has no representation: an operation that would
+/// reach it fails, mod the primes of n where it is reached.
+#[derive(Clone, Debug)]
+pub struct WeierstrassCurve<R: ModularRing> {
+    ring: R,
+    a: R::Elem,
+    b: R::Elem,
+}
+
+/// Affine point (x, y) on a `WeierstrassCurve`
+#[derive(Clone, PartialEq, Debug)]
+pub struct WeierstrassPoint<R: ModularRing> {
+    pub x: R::Elem,
+    pub y: R::Elem,
+}
+
+impl<R: ModularRing> WeierstrassCurve<R> {
+    /// Weierstrass curve given by a and b mod modulo
+    pub fn new(a: Integer, b: Integer, modulo: Integer) -> WeierstrassCurve<R> {
+        let ring = R::new(&modulo).unwrap();
+        WeierstrassCurve::with_ring(ring.to_residue(&a), ring.to_residue(&b), ring)
+    }
+
+    /// Weierstrass curve whose a and b are already residues of ring
+    pub fn with_ring(a: R::Elem, b: R::Elem, ring: R) -> WeierstrassCurve<R> {
+        WeierstrassCurve { ring, a, b }
+    }
+
+    /// The curve isomorphic to the Montgomery curve By^2 = x^3 + Ax^2 + x,
+    /// a = (3 - A^2)/3B^2 and b = (2A^3 - 9A)/27B^3, and the image
+    /// ((3x + A)/3B, y/B) of its point p. Fails with the gcd with n of 3B
+    /// or of the Z of p if one is not invertible.
+    pub fn from_montgomery(
+        curve: &FullCurve<R>,
+        p: &FullPoint<R>,
+    ) -> Result<(WeierstrassCurve<R>, WeierstrassPoint<R>), Integer> {
+        let ring = curve.ring();
+        let invert = |a: &R::Elem| ring.invert(a).ok_or_else(|| ring.gcd(a));
+        let (a, b) = (curve.a(), curve.b());
+        let three = ring.add(&ring.add(&ring.one(), &ring.one()), &ring.one());
+        // t = 1/3B, so that 1/B = 3t, 1/3B^2 = 3t^2 and 1/27B^3 = t^3
+        let t = invert(&ring.mul(&three, b))?;
+        let z = invert(&p.z)?;
+        let t2 = ring.square(&t);
+        let a2 = ring.square(a);
+        let wa = ring.mul(&ring.sub(&three, &a2), &ring.mul(&three, &t2));
+        // 2A^3 - 9A = A (2A^2 - 9)
+        let nine = ring.mul(&three, &three);
+        let wb = ring.mul(&ring.sub(&ring.add(&a2, &a2), &nine), a);
+        let wb = ring.mul(&wb, &ring.mul(&t2, &t));
+        // x = (3X/Z + A) t, y = 3 (Y/Z) t
+        let x = ring.add(&ring.mul(&three, &ring.mul(&p.x, &z)), a);
+        let point = WeierstrassPoint {
+            x: ring.mul(&x, &t),
+            y: ring.mul(&ring.mul(&three, &ring.mul(&p.y, &z)), &t),
+        };
+        Ok((WeierstrassCurve::with_ring(wa, wb, ring.clone()), point))
+    }
+
+    /// The Montgomery curve (1/s)y^2 = x^3 + (3 alpha/s)x^2 + x isomorphic
+    /// to this one, for a root alpha of x^3 + ax + b and s with s^2 =
+    /// 3 alpha^2 + a, and the image ((x - alpha)/s, y/s) of its point p.
+    /// Fails with the gcd of s with n if s is not invertible.
+    ///
+    /// For a curve of `from_montgomery`, alpha = A/3B and s = 1/B.
+    pub fn to_montgomery(
+        &self,
+        p: &WeierstrassPoint<R>,
+        alpha: &R::Elem,
+        s: &R::Elem,
+    ) -> Result<(FullCurve<R>, FullPoint<R>), Integer> {
+        let ring = &self.ring;
+        let b = ring.invert(s).ok_or_else(|| ring.gcd(s))?;
+        let three = ring.add(&ring.add(&ring.one(), &ring.one()), &ring.one());
+        let a = ring.mul(&ring.mul(&three, alpha), &b);
+        let point = FullPoint {
+            x: ring.sub(&p.x, alpha),
+            y: p.y.clone(),
+            z: s.clone(),
+        };
+        Ok((FullCurve::with_ring(a, b, ring.clone()), point))
+    }
+
+    pub fn ring(&self) -> &R {
+        &self.ring
+    }
+
+    pub fn a(&self) -> &R::Elem {
+        &self.a
+    }
+
+    pub fn b(&self) -> &R::Elem {
+        &self.b
+    }
+
+    /// (a, b) as integers, e.g. for ellinit([a, b]) in PARI/GP or
+    /// EllipticCurve(Zmod(n), [a, b]) in Sage
+    pub fn coefficients(&self) -> (Integer, Integer) {
+        (self.ring.to_integer(&self.a), self.ring.to_integer(&self.b))
+    }
+
+    /// (x, y) of p as integers
+    pub fn coordinates(&self, p: &WeierstrassPoint<R>) -> (Integer, Integer) {
+        (self.ring.to_integer(&p.x), self.ring.to_integer(&p.y))
+    }
+
+    /// Affine point (x, y) on this curve
+    pub fn point(&self, x: &Integer, y: &Integer) -> WeierstrassPoint<R> {
+        WeierstrassPoint {
+            x: self.ring.to_residue(x),
+            y: self.ring.to_residue(y),
+        }
+    }
+
+    /// Whether y^2 = x^3 + ax + b
+    pub fn is_on_curve(&self, p: &WeierstrassPoint<R>) -> bool {
+        let ring = &self.ring;
+        let mut rhs = ring.square(&p.x);
+        ring.add_assign(&mut rhs, &self.a);
+        ring.mul_assign(&mut rhs, &p.x);
+        ring.add_assign(&mut rhs, &self.b);
+        ring.square(&p.y) == rhs
+    }
+
+    /// num/den, or the gcd of den with n
+    fn divide(&self, num: &R::Elem, den: &R::Elem) -> Result<R::Elem, Integer> {
+        let ring = &self.ring;
+        let inv = ring.invert(den).ok_or_else(|| ring.gcd(den))?;
+        Ok(ring.mul(num, &inv))
+    }
+
+    /// The third intersection of the line of slope l through p and q,
+    /// negated
+    fn chord(
+        &self,
+        p: &WeierstrassPoint<R>,
+        q: &WeierstrassPoint<R>,
+        l: &R::Elem,
+    ) -> WeierstrassPoint<R> {
+        let ring = &self.ring;
+        let mut x = ring.square(l);
+        ring.sub_assign(&mut x, &p.x);
+        ring.sub_assign(&mut x, &q.x);
+        let mut y = ring.sub(&p.x, &x);
+        ring.mul_assign(&mut y, l);
+        ring.sub_assign(&mut y, &p.y);
+        WeierstrassPoint { x, y }
+    }
+
+    /// p + q, doubling if p = q. Fails with the gcd with n of the
+    /// denominator x_q - x_p, which is n for q = -p.
+    pub fn add(
+        &self,
+        p: &WeierstrassPoint<R>,
+        q: &WeierstrassPoint<R>,
+    ) -> Result<WeierstrassPoint<R>, Integer> {
+        if p == q {
+            return self.double(p);
+        }
+        let ring = &self.ring;
+        let l = self.divide(&ring.sub(&q.y, &p.y), &ring.sub(&q.x, &p.x))?;
+        Ok(self.chord(p, q, &l))
+    }
+
+    /// [2]p, or the gcd of 2y with n
+    pub fn double(&self, p: &WeierstrassPoint<R>) -> Result<WeierstrassPoint<R>, Integer> {
+        let ring = &self.ring;
+        // (3x^2 + a) / 2y
+        let x2 = ring.square(&p.x);
+        let mut num = ring.add(&x2, &x2);
+        ring.add_assign(&mut num, &x2);
+        ring.add_assign(&mut num, &self.a);
+        let l = self.divide(&num, &ring.add(&p.y, &p.y))?;
+        Ok(self.chord(p, p, &l))
+    }
+
+    /// [k]p for k >= 1 by double and add, or the gcd with n of the first
+    /// denominator that is not invertible
+    pub fn mul(
+        &self,
+        p: &WeierstrassPoint<R>,
+        k: &Integer,
+    ) -> Result<WeierstrassPoint<R>, Integer> {
+        assert!(*k >= 1, "k must be positive");
+        let mut q = p.clone();
+        for i in (0..k.significant_bits() - 1).rev() {
+            q = self.double(&q)?;
+            if k.get_bit(i) {
+                q = self.add(&q, p)?;
+            }
+        }
+        Ok(q)
+    }
+}
+
+/// [k]p_i for the points p_i of the curves, all in lockstep: each doubling
+/// and each addition of the double and add chain takes one inversion for
+/// the whole batch, by Montgomery's trick, instead of one per curve.
+///
+/// Fails with the first proper factor of n a denominator reveals. A curve
+/// whose denominator is zero mod n gives no factor and is dropped: the
+/// result tells which curves are left.
+pub fn mul_batch<R: ModularRing>(
+    curves: &[WeierstrassCurve<R>],
+    points: &mut [WeierstrassPoint<R>],
+    k: &Integer,
+) -> Result<Vec<bool>, Integer> {
+    assert!(*k >= 1, "k must be positive");
+    assert_eq!(curves.len(), points.len());
+    let mut live = vec![true; points.len()];
+    let ring = match curves.first() {
+        Some(curve) => curve.ring(),
+        None => return Ok(live),
+    };
+    let start = points.to_vec();
+    let mut den = vec![ring.one(); points.len()];
+    for i in (0..k.significant_bits() - 1).rev() {
+        // the slopes (3x^2 + a)/2y
+        for ((d, p), l) in den.iter_mut().zip(points.iter()).zip(live.iter()) {
+            if *l {
+                ring.add_into(d, &p.y, &p.y);
+            }
+        }
+        invert_live(ring, &mut den, &mut live)?;
+        for (j, (curve, p)) in curves.iter().zip(points.iter_mut()).enumerate() {
+            if live[j] {
+                let x2 = ring.square(&p.x);
+                let mut l = ring.add(&x2, &x2);
+                ring.add_assign(&mut l, &x2);
+                ring.add_assign(&mut l, &curve.a);
+                ring.mul_assign(&mut l, &den[j]);
+                *p = curve.chord(p, p, &l);
+            }
+        }
+        if k.get_bit(i) {
+            // the slopes (y_0 - y)/(x_0 - x) to the starting points
+            for (j, (d, p)) in den.iter_mut().zip(points.iter()).enumerate() {
+                if live[j] {
+                    ring.sub_into(d, &start[j].x, &p.x);
+                }
+            }
+            invert_live(ring, &mut den, &mut live)?;
+            for (j, (curve, p)) in curves.iter().zip(points.iter_mut()).enumerate() {
+                if live[j] {
+                    let l = ring.mul(&ring.sub(&start[j].y, &p.y), &den[j]);
+                    *p = curve.chord(p, &start[j], &l);
+                }
+            }
+        }
+    }
+    Ok(live)
+}
+
+/// Inverts the denominators of the live curves at once. When that fails
+/// without a proper factor, some denominators are zero mod n: their
+/// curves are dropped, and the others inverted.
+fn invert_live<R: ModularRing>(
+    ring: &R,
+    den: &mut [R::Elem],
+    live: &mut [bool],
+) -> Result<(), Integer> {
+    match ring.batch_invert(den) {
+        Ok(()) => return Ok(()),
+        Err(g) if g < *ring.modulus() => return Err(g),
+        Err(_) => {}
+    }
+    // the product is zero mod n, maybe from denominators zero mod
+    // different primes
+    for (d, l) in den.iter_mut().zip(live.iter_mut()) {
+        let g = ring.gcd(d);
+        if g == *ring.modulus() {
+            *l = false;
+            *d = ring.one();
+        } else if g > 1 {
+            return Err(g);
+        }
+    }
+    ring.batch_invert(den)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::integer::Complete;
+    use crate::arith::montgomery_point::MontgomeryCurve;
+    use crate::arith::ring::DefaultRing;
+
+    #[test]
+    fn weierstrass_tests() {
+        // the Montgomery curve A = 10 mod 1000003 through x = 5 with y = 1
+        let n = Integer::from(1000003);
+        MontgomeryCurve::<DefaultRing>::with_a(Integer::from(10), n.clone(), |montgomery| {
+            let xz = montgomery.point(&Integer::from(5), &Integer::from(1));
+            let (full, p) = montgomery.lift(&xz).unwrap();
+            let (curve, q) = WeierstrassCurve::from_montgomery(&full, &p).unwrap();
+            assert!(curve.is_on_curve(&q));
+
+            let q2 = curve.double(&q).unwrap();
+            let q3 = curve.add(&q2, &q).unwrap();
+            assert!(curve.is_on_curve(&q2) && curve.is_on_curve(&q3));
+            assert_eq!(curve.add(&q, &q2).unwrap(), q3);
+            assert_eq!(curve.add(&q, &q).unwrap(), q2);
+            // mul agrees with the Montgomery ladder, through 3x = 3Bu - A
+            let ring = curve.ring();
+            let three = Integer::from(3);
+            for k in [1u64, 2, 3, 10, 1000, 123456] {
+                let k = Integer::from(k);
+                let r = curve.mul(&q, &k).unwrap();
+                assert!(curve.is_on_curve(&r));
+                let (x, z) = montgomery.montgomery_ladder(&xz, &k).coordinates();
+                let u = ring.sub(
+                    &ring.mul(&ring.to_residue(&three), &ring.mul(full.b(), &r.x)),
+                    full.a(),
+                );
+                let u = ring.to_integer(&u);
+                assert_eq!(
+                    (&u * &z).complete() % &n,
+                    (&x * &three).complete() % &n,
+                    "k={}",
+                    k
+                );
+            }
+            // p + -p has no affine representation
+            let neg = WeierstrassPoint {
+                x: q.x.clone(),
+                y: ring.sub(&ring.zero(), &q.y),
+            };
+            assert_eq!(curve.add(&q, &neg), Err(n));
+        });
+    }
+
+    /// Montgomery to Weierstrass and back gives the same curve and point
+    #[test]
+    fn weierstrass_montgomery_tests() {
+        let n = Integer::from(1000003u64 * 999983);
+        let (full, p) = MontgomeryCurve::<DefaultRing>::with_a(Integer::from(10), n, |m| {
+            m.lift(&m.point(&Integer::from(5), &Integer::from(1)))
+                .unwrap()
+        });
+        let (curve, q) = WeierstrassCurve::from_montgomery(&full, &p).unwrap();
+        let ring = curve.ring();
+        // x = alpha, image of the point (0, 0) of order 2, is a root
+        let three = ring.to_residue(&Integer::from(3));
+        let s = ring.invert(full.b()).unwrap();
+        let alpha = ring.mul(full.a(), &ring.mul(&s, &ring.invert(&three).unwrap()));
+        let root = WeierstrassPoint {
+            x: alpha.clone(),
+            y: ring.zero(),
+        };
+        assert!(curve.is_on_curve(&root));
+        let (a, b) = curve.coefficients();
+        assert_eq!(ring.to_residue(&a), *curve.a());
+        assert_eq!(ring.to_residue(&b), *curve.b());
+
+        let (back, r) = curve.to_montgomery(&q, &alpha, &s).unwrap();
+        assert_eq!((back.a(), back.b()), (full.a(), full.b()));
+        assert!(back.is_on_curve(&r));
+        let z = ring.invert(&r.z).unwrap();
+        assert_eq!((ring.mul(&r.x, &z), ring.mul(&r.y, &z)), (p.x, p.y));
+    }
+
+    /// A denominator that vanishes mod one prime of n reveals it
+    #[test]
+    fn weierstrass_factor_tests() {
+        // (0, p) on y^2 = x^3 + x + p^2 has order 2 mod p only
+        let (p, q) = (1000003u64, 999983u64);
+        let n = Integer::from(p * q);
+        let b = Integer::from(p) * p;
+        let curve = WeierstrassCurve::<DefaultRing>::new(Integer::from(1), b, n);
+        let point = curve.point(&Integer::from(0), &Integer::from(p));
+        assert!(curve.is_on_curve(&point));
+        assert_eq!(curve.double(&point), Err(Integer::from(p)));
+        assert_eq!(curve.mul(&point, &Integer::from(6)), Err(Integer::from(p)));
+    }
+
+    /// Batched, the points are those of mul, a curve with a denominator
+    /// zero mod n is dropped, and one zero mod a prime reveals it
+    #[test]
+    fn mul_batch_tests() {
+        let (p, q) = (1000003u64, 999983u64);
+        let n = Integer::from(p * q);
+        let mut curves = vec![];
+        let mut points = vec![];
+        for a in 10..20 {
+            let (full, p) =
+                MontgomeryCurve::<DefaultRing>::with_a(Integer::from(a), n.clone(), |m| {
+                    m.lift(&m.point(&Integer::from(5), &Integer::from(1)))
+                        .unwrap()
+                });
+            let (curve, p) = WeierstrassCurve::from_montgomery(&full, &p).unwrap();
+            curves.push(curve);
+            points.push(p);
+        }
+        // (0, 0) on y^2 = x^3 + x has order 2 mod n
+        let ring = curves[0].ring().clone();
+        curves.push(WeierstrassCurve::with_ring(
+            ring.one(),
+            ring.zero(),
+            ring.clone(),
+        ));
+        points.push(WeierstrassPoint {
+            x: ring.zero(),
+            y: ring.zero(),
+        });
+        for k in [1u64, 2, 3, 10, 1000, 123456] {
+            let k = Integer::from(k);
+            let mut batch = points.clone();
+            let live = mul_batch(&curves, &mut batch, &k).unwrap();
+            for (i, curve) in curves.iter().enumerate() {
+                match curve.mul(&points[i], &k) {
+                    Ok(r) => assert!(live[i] && batch[i] == r),
+                    Err(g) => assert!(!live[i] && g == n),
+                }
+            }
+        }
+
+        // (0, p) on y^2 = x^3 + x + p^2 has order 2 mod p only
+        let b = ring.to_residue(&(Integer::from(p) * p));
+        curves.push(WeierstrassCurve::with_ring(ring.one(), b, ring.clone()));
+        points.push(curves[curves.len() - 1].point(&Integer::from(0), &Integer::from(p)));
+        assert_eq!(
+            mul_batch(&curves, &mut points, &Integer::from(6)),
+            Err(Integer::from(p))
+        );
+    }
+}
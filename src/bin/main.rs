@@ -1,11 +1,13 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use ecm::ecm_multithreaded;
 use ecm::ecm_singlethreaded;
+use ecm::Integer;
+use ecm::{ecm_resume, Checkpoints, Parametrization, Residue, SaveFile, Torsion};
+use ecm::{Continuation, Stage1Plan, Stage2Plan, SuyamaPolynomial};
 use log::info;
-use rug::Integer;
-use simple_logger;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 extern crate hwloc;
 use hwloc::{ObjectType, Topology};
 
@@ -20,7 +22,7 @@ fn main() {
                 .takes_value(true)
                 .short('n')
                 .long("number")
-                .required(true),
+                .required_unless_present("resume"),
         )
         .arg(
             Arg::new("num_curves")
@@ -60,14 +62,108 @@ fn main() {
                 .long("b2")
                 .required(false),
         )
+        .arg(
+            Arg::new("b2min")
+                .about("Start of stage 2, to extend an earlier stage 2 or split it into ranges (default: B1 + 1)")
+                .takes_value(true)
+                .long("b2min")
+                .required(false),
+        )
         .arg(
             Arg::new("sigma")
-                .about("Curve's parameter")
+                .about("Curve's parameter, optionally prefixed by the parametrization as in param:sigma")
                 .takes_value(true)
                 .short('s')
                 .long("sigma")
                 .required(false),
         )
+        .arg(
+            Arg::new("param")
+                .about("Parametrization of the curves, numbered as GMP-ECM's: 0 Suyama, 1 and 3 the batch ones, 2 the Z/12 torsion family (default: 0)")
+                .takes_value(true)
+                .long("param")
+                .required(false),
+        )
+        .arg(
+            Arg::new("torsion")
+                .about("Use Edwards curves with torsion Z12 or Z2xZ8 instead of a parametrization")
+                .takes_value(true)
+                .long("torsion")
+                .conflicts_with("param")
+                .required(false),
+        )
+        .arg(
+            Arg::new("special_form")
+                .about("Work modulo b^k+-1 if n divides such a number (e.g. Fermat or Mersenne cofactors)")
+                .takes_value(false)
+                .long("special_form")
+                .required(false),
+        )
+        .arg(
+            Arg::new("fft")
+                .about("Run stage 2 by polynomial arithmetic (the FFT continuation), for large B2")
+                .takes_value(false)
+                .long("fft")
+                .required(false),
+        )
+        .arg(
+            Arg::new("power")
+                .about("Brent-Suyama extension of stage 2 with x^e, for the given degree e")
+                .takes_value(true)
+                .long("power")
+                .conflicts_with("dickson")
+                .required(false),
+        )
+        .arg(
+            Arg::new("dickson")
+                .about("Brent-Suyama extension of stage 2 with the Dickson polynomial of the given degree")
+                .takes_value(true)
+                .long("dickson")
+                .required(false),
+        )
+        .arg(
+            Arg::new("save")
+                .about("Append the stage 1 residue of each curve to this file, in GMP-ECM's format")
+                .takes_value(true)
+                .long("save")
+                .required(false),
+        )
+        .arg(
+            Arg::new("resume")
+                .about("Run stage 2 only, on the stage 1 residues of this file (ours or GMP-ECM's)")
+                .takes_value(true)
+                .long("resume")
+                .conflicts_with("number")
+                .required(false),
+        )
+        .arg(
+            Arg::new("checkpoint")
+                .about("Checkpoint stage 1 to this file (one per thread, suffixed by its number), and resume from it")
+                .takes_value(true)
+                .long("checkpoint")
+                .required(false),
+        )
+        .arg(
+            Arg::new("checkpoint_interval")
+                .about("Seconds between two checkpoints (default: 600)")
+                .takes_value(true)
+                .long("checkpoint_interval")
+                .requires("checkpoint")
+                .required(false),
+        )
+        .arg(
+            Arg::new("batch")
+                .about("Run stage 1 on batches of this many curves per thread (e.g. 64), in affine coordinates with one inversion per step for the batch")
+                .takes_value(true)
+                .long("batch")
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(0) => Err("the batch size must be positive".to_string()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.to_string()),
+                })
+                .conflicts_with_all(&["sigma", "checkpoint", "single_threaded"])
+                .required(false),
+        )
         .arg(
             Arg::new("single_threaded")
                 .about("Run on a single thread\nNote: the program is multi-threaded by default, using as many threads as there are cores available")
@@ -89,7 +185,9 @@ fn main() {
             .unwrap();
     };
 
-    if let Some(n) = matches.value_of("number") {
+    if let Some(path) = matches.value_of("resume") {
+        resume(&matches, path);
+    } else if let Some(n) = matches.value_of("number") {
         match Integer::from_str(n) {
             Err(_) => println!("Wrong input"),
             Ok(n) => {
@@ -97,22 +195,45 @@ fn main() {
                     Some(s) => s.parse::<u64>().unwrap(),
                     None => 10000,
                 };
-                let b2: u64 = match matches.value_of("b2_bound") {
-                    Some(s) => s.parse::<u64>().unwrap(),
-                    None => 100 * b1,
-                };
                 let curves = Arc::new(
                     matches
                         .value_of("num_curves")
                         .and_then(|s| Integer::from_str(s).ok()),
                 );
-                let sigma = matches
-                    .value_of("sigma")
-                    .and_then(|s| Integer::from_str(s).ok());
-                if matches.is_present("single_threaded") || !sigma.is_none() {
-                    match ecm_singlethreaded(&n, &curves, b1, b2, &Arc::new(sigma)) {
-                        Some(f) => print!("Found factor {}.\n", f),
-                        None => print!("No factor found.\n"),
+                let (param, sigma) = match curves_of(&matches) {
+                    Ok(curves) => curves,
+                    Err(e) => return println!("{}", e),
+                };
+                let special_form = matches.is_present("special_form");
+                let batch = matches
+                    .value_of("batch")
+                    .map(|s| s.parse::<usize>().unwrap());
+                let stage1 = Arc::new(Stage1Plan::new(b1));
+                let stage2 = Arc::new(stage2_plan(&matches, b1));
+                let save = matches.value_of("save").map(|path| {
+                    Arc::new(SaveFile::append_to(path).expect("cannot open the save file"))
+                });
+                let checkpoints = matches.value_of("checkpoint").map(|path| {
+                    let seconds = match matches.value_of("checkpoint_interval") {
+                        Some(s) => s.parse::<u64>().unwrap(),
+                        None => 600,
+                    };
+                    Arc::new(Checkpoints::new(path, Duration::from_secs(seconds)))
+                });
+                if matches.is_present("single_threaded") || sigma.is_some() {
+                    match ecm_singlethreaded(
+                        &n,
+                        &curves,
+                        &stage1,
+                        &stage2,
+                        &Arc::new(sigma),
+                        param,
+                        special_form,
+                        save.as_deref(),
+                        checkpoints.as_deref(),
+                    ) {
+                        Some(f) => println!("Found factor {}.", f),
+                        None => println!("No factor found."),
                     }
                 } else {
                     let topology = Topology::new();
@@ -125,12 +246,109 @@ fn main() {
                     };
                     info!("Found {} cores, spawning {} threads", nthreads, nthreads);
 
-                    match ecm_multithreaded(&n, &curves, b1, b2, &Arc::new(sigma), nthreads) {
-                        Some(f) => print!("Found factor {}.\n", f),
-                        None => print!("No factor found.\n"),
+                    match ecm_multithreaded(
+                        &n,
+                        &curves,
+                        &stage1,
+                        &stage2,
+                        &Arc::new(sigma),
+                        param,
+                        nthreads,
+                        batch,
+                        special_form,
+                        &save,
+                        &checkpoints,
+                    ) {
+                        Some(f) => println!("Found factor {}.", f),
+                        None => println!("No factor found."),
                     }
                 }
             }
         }
     }
 }
+
+/// Parametrization and sigma from the command line, sigma being either
+/// a number or param:sigma as GMP-ECM writes it, or the torsion family
+/// of the Edwards curves
+fn curves_of(matches: &ArgMatches) -> Result<(Parametrization, Option<Integer>), String> {
+    let param = |p: &str| {
+        p.parse::<u32>()
+            .ok()
+            .and_then(Parametrization::from_param)
+            .ok_or_else(|| format!("Unknown param {}", p))
+    };
+    let mut parametrization = match (matches.value_of("param"), matches.value_of("torsion")) {
+        (Some(p), _) => param(p)?,
+        (None, Some(t)) => Parametrization::Edwards(t.parse::<Torsion>()?),
+        (None, None) => Parametrization::Suyama,
+    };
+    let sigma = match matches.value_of("sigma") {
+        Some(s) => {
+            let s = match s.split_once(':') {
+                Some((p, s)) => {
+                    let p = param(p)?;
+                    let given = matches.is_present("param") || matches.is_present("torsion");
+                    if given && p != parametrization {
+                        return Err(format!("Sigma {} is not for param {}", s, parametrization));
+                    }
+                    parametrization = p;
+                    s
+                }
+                None => s,
+            };
+            Some(Integer::from_str(s).map_err(|_| format!("Wrong sigma {}", s))?)
+        }
+        None => None,
+    };
+    Ok((parametrization, sigma))
+}
+
+/// Stage 2 plan from the command line, for stage 1 done to b1
+fn stage2_plan(matches: &ArgMatches, b1: u64) -> Stage2Plan {
+    let b2: u64 = match matches.value_of("b2_bound") {
+        Some(s) => s.parse::<u64>().unwrap(),
+        None => 100 * b1,
+    };
+    let b2min: u64 = match matches.value_of("b2min") {
+        Some(s) => s.parse::<u64>().unwrap(),
+        None => b1 + 1,
+    };
+    let continuation = if matches.is_present("fft") {
+        Continuation::Fft
+    } else {
+        Continuation::Standard
+    };
+    let stage2 = Stage2Plan::range(continuation, b2min, b2);
+    if let Some(e) = matches.value_of("power") {
+        stage2.with_brent_suyama(SuyamaPolynomial::Power(e.parse().unwrap()))
+    } else if let Some(e) = matches.value_of("dickson") {
+        stage2.with_brent_suyama(SuyamaPolynomial::Dickson(e.parse().unwrap()))
+    } else {
+        stage2
+    }
+}
+
+/// Stage 2 on each residue of the save file at path, up to the first
+/// factor found
+fn resume(matches: &ArgMatches, path: &str) {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => return println!("Cannot read {}: {}", path, e),
+    };
+    let residues = match Residue::parse_all(&contents) {
+        Ok(residues) => residues,
+        Err(e) => return println!("Wrong save file: {}", e),
+    };
+    for residue in residues {
+        info!(
+            "Resuming sigma={}:{} on {}",
+            residue.param, residue.sigma, residue.n
+        );
+        let stage2 = stage2_plan(matches, residue.b1);
+        if let Some(f) = ecm_resume(&residue, &stage2) {
+            return println!("Found factor {} of {}.", f, residue.n);
+        }
+    }
+    println!("No factor found.");
+}
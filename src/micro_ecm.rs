@@ -0,0 +1,473 @@
+//! ECM for numbers below 2^127, done entirely in native arithmetic.
+//!
+//! Meant for cofactorization, where one has to split millions of 40 to
+//! 126-bit numbers: no arbitrary precision `Integer`, no prime table and no thread is
+//! involved, stage 1 runs along a chain computed at compile time for a
+//! few fixed bounds, and stage 2 is a short baby step giant step loop.
+use crate::arith::fixed_ring::{add_n, cmp_n, mont_mul, neg_inverse, sub_n};
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+
+/// Stage 1 bounds micro-ECM has a precomputed chain for.
+/// Stage 2 goes up to 25 * B1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MicroB1 {
+    B47,
+    B85,
+    B125,
+    B165,
+    B205,
+}
+
+impl MicroB1 {
+    /// Usual choice of B1 for a number of the given size, from the
+    /// expected size of its smallest factor
+    pub fn for_bits(bits: u32) -> MicroB1 {
+        match bits {
+            0..=52 => MicroB1::B47,
+            53..=64 => MicroB1::B85,
+            65..=84 => MicroB1::B125,
+            85..=100 => MicroB1::B165,
+            _ => MicroB1::B205,
+        }
+    }
+
+    pub fn b1(self) -> u64 {
+        match self {
+            MicroB1::B47 => 47,
+            MicroB1::B85 => 85,
+            MicroB1::B125 => 125,
+            MicroB1::B165 => 165,
+            MicroB1::B205 => 205,
+        }
+    }
+
+    pub fn b2(self) -> u64 {
+        25 * self.b1()
+    }
+
+    /// The giant steps m of stage 2, up to the last one whose primes
+    /// mD +- j, j < D/2, reach into (B1, B2]
+    fn giant_steps(self) -> RangeInclusive<u64> {
+        1..=(self.b2() + D / 2) / D
+    }
+
+    /// The scalars stage 1 multiplies the point by, in turn
+    fn chain(self) -> &'static [u64] {
+        match self {
+            MicroB1::B47 => &CHAIN_47,
+            MicroB1::B85 => &CHAIN_85,
+            MicroB1::B125 => &CHAIN_125,
+            MicroB1::B165 => &CHAIN_165,
+            MicroB1::B205 => &CHAIN_205,
+        }
+    }
+}
+
+const fn is_small_prime(p: u64) -> bool {
+    if p < 2 {
+        return false;
+    }
+    let mut d = 2;
+    while d * d <= p {
+        if p.is_multiple_of(d) {
+            return false;
+        }
+        d += 1;
+    }
+    true
+}
+
+/// Walks the product of the largest powers of the primes up to b1 that
+/// are <= b1, cut into factors that fit in a u64. Returns the number of
+/// factors, which are written to chain if it is long enough.
+const fn stage1_chain(b1: u64, chain: &mut [u64]) -> usize {
+    let mut len = 0;
+    let mut acc: u64 = 1;
+    let mut p = 2;
+    while p <= b1 {
+        if is_small_prime(p) {
+            let mut q = p;
+            while q * p <= b1 {
+                q *= p;
+            }
+            match acc.checked_mul(q) {
+                Some(a) => acc = a,
+                None => {
+                    if len < chain.len() {
+                        chain[len] = acc;
+                    }
+                    len += 1;
+                    acc = q;
+                }
+            }
+        }
+        p += 1;
+    }
+    if len < chain.len() {
+        chain[len] = acc;
+    }
+    len + 1
+}
+
+const fn stage1_chain_len(b1: u64) -> usize {
+    stage1_chain(b1, &mut [])
+}
+
+const fn precomputed_chain<const L: usize>(b1: u64) -> [u64; L] {
+    let mut chain = [0; L];
+    stage1_chain(b1, &mut chain);
+    chain
+}
+
+static CHAIN_47: [u64; stage1_chain_len(47)] = precomputed_chain(47);
+static CHAIN_85: [u64; stage1_chain_len(85)] = precomputed_chain(85);
+static CHAIN_125: [u64; stage1_chain_len(125)] = precomputed_chain(125);
+static CHAIN_165: [u64; stage1_chain_len(165)] = precomputed_chain(165);
+static CHAIN_205: [u64; stage1_chain_len(205)] = precomputed_chain(205);
+
+/// Stage 2 giant step, and the baby steps j < D/2 coprime to D
+const D: u64 = 60;
+const BABY_STEPS: [u64; 8] = [1, 7, 11, 13, 17, 19, 23, 29];
+
+fn to_limbs<const N: usize>(a: u128) -> [u64; N] {
+    let mut r = [0; N];
+    for (i, l) in r.iter_mut().enumerate() {
+        *l = (a >> (64 * i)) as u64;
+    }
+    r
+}
+
+fn from_limbs<const N: usize>(a: &[u64; N]) -> u128 {
+    a.iter()
+        .enumerate()
+        .fold(0, |acc, (i, &l)| acc | (l as u128) << (64 * i))
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// Inverse of a mod n for n < 2^127, or Err(gcd(a, n)) if there is none
+fn invert(a: u128, n: u128) -> Result<u128, u128> {
+    let (mut r0, mut r1) = (n as i128, (a % n) as i128);
+    let (mut t0, mut t1) = (0i128, 1i128);
+    while r1 != 0 {
+        let q = r0 / r1;
+        (r0, r1) = (r1, r0 - q * r1);
+        (t0, t1) = (t1, t0 - q * t1);
+    }
+    if r0 != 1 {
+        return Err(r0 as u128);
+    }
+    Ok(t0.rem_euclid(n as i128) as u128)
+}
+
+/// Z/nZ in Montgomery representation with N = 1 or 2 limbs, i.e. u64 or
+/// u128 arithmetic
+struct MicroRing<const N: usize> {
+    n: u128,
+    n_limbs: [u64; N],
+    n_inv: u64,
+    /// R mod n, R = 2^(64*N)
+    one: [u64; N],
+    /// R^2 mod n
+    r2: [u64; N],
+}
+
+impl<const N: usize> MicroRing<N> {
+    fn new(n: u128) -> MicroRing<N> {
+        let n_limbs = to_limbs(n);
+        let r_mod_n = if N == 1 {
+            (1u128 << 64) % n
+        } else {
+            (u128::MAX % n + 1) % n
+        };
+        let mut ring = MicroRing {
+            n,
+            n_limbs,
+            n_inv: neg_inverse(n_limbs[0]),
+            one: to_limbs(r_mod_n),
+            r2: to_limbs(r_mod_n),
+        };
+        // R^2 = R.2^(64*N)
+        for _ in 0..64 * N {
+            let r = ring.r2;
+            ring.r2 = ring.add(&r, &r);
+        }
+        ring
+    }
+
+    #[inline(always)]
+    fn add(&self, a: &[u64; N], b: &[u64; N]) -> [u64; N] {
+        let mut r = [0; N];
+        if add_n(&mut r, a, b) || cmp_n(&r, &self.n_limbs) != Ordering::Less {
+            let s = r;
+            sub_n(&mut r, &s, &self.n_limbs);
+        }
+        r
+    }
+
+    #[inline(always)]
+    fn sub(&self, a: &[u64; N], b: &[u64; N]) -> [u64; N] {
+        let mut r = [0; N];
+        if sub_n(&mut r, a, b) {
+            let s = r;
+            add_n(&mut r, &s, &self.n_limbs);
+        }
+        r
+    }
+
+    #[inline(always)]
+    fn mul(&self, a: &[u64; N], b: &[u64; N]) -> [u64; N] {
+        let mut r = [0; N];
+        mont_mul(&mut r, a, b, &self.n_limbs, self.n_inv);
+        r
+    }
+
+    fn to_residue(&self, a: u128) -> [u64; N] {
+        self.mul(&to_limbs(a % self.n), &self.r2)
+    }
+
+    fn to_u128(&self, a: &[u64; N]) -> u128 {
+        from_limbs(&self.mul(a, &to_limbs(1)))
+    }
+
+    /// gcd(a, n), on the Montgomery representation since R is coprime to n
+    fn gcd(&self, a: &[u64; N]) -> u128 {
+        gcd(from_limbs(a), self.n)
+    }
+}
+
+/// A point in Montgomery's XZ coordinates
+#[derive(Clone, Copy)]
+struct Point<const N: usize> {
+    x: [u64; N],
+    z: [u64; N],
+}
+
+/// x-only arithmetic on By^2 = x^3 + Ax^2 + x, a24 = (A+2)/4
+struct MicroCurve<const N: usize> {
+    ring: MicroRing<N>,
+    a24: [u64; N],
+}
+
+impl<const N: usize> MicroCurve<N> {
+    #[inline(always)]
+    fn double(&self, p: &Point<N>) -> Point<N> {
+        let r = &self.ring;
+        let s = r.add(&p.x, &p.z);
+        let s = r.mul(&s, &s);
+        let d = r.sub(&p.x, &p.z);
+        let d = r.mul(&d, &d);
+        let t = r.sub(&s, &d);
+        Point {
+            x: r.mul(&s, &d),
+            z: r.mul(&t, &r.add(&d, &r.mul(&self.a24, &t))),
+        }
+    }
+
+    /// p + q given diff = p - q
+    #[inline(always)]
+    fn add(&self, p: &Point<N>, q: &Point<N>, diff: &Point<N>) -> Point<N> {
+        let r = &self.ring;
+        let u = r.mul(&r.sub(&p.x, &p.z), &r.add(&q.x, &q.z));
+        let v = r.mul(&r.add(&p.x, &p.z), &r.sub(&q.x, &q.z));
+        let s = r.add(&u, &v);
+        let d = r.sub(&u, &v);
+        Point {
+            x: r.mul(&diff.z, &r.mul(&s, &s)),
+            z: r.mul(&diff.x, &r.mul(&d, &d)),
+        }
+    }
+
+    /// [k]p using Montgomery's ladder, k > 0
+    fn ladder(&self, p: &Point<N>, k: u64) -> Point<N> {
+        let mut r0 = *p;
+        let mut r1 = self.double(p);
+        for i in (0..63 - k.leading_zeros()).rev() {
+            if (k >> i) & 1 == 1 {
+                r0 = self.add(&r1, &r0, p);
+                r1 = self.double(&r1);
+            } else {
+                r1 = self.add(&r1, &r0, p);
+                r0 = self.double(&r0);
+            }
+        }
+        r0
+    }
+}
+
+/// Runs one curve of Suyama's family with parameter sigma.
+/// Returns gcd(n, ...) at the end of stage 2, or at setup if the curve
+/// turns out to be singular mod n.
+fn micro_curve<const N: usize>(n: u128, sigma: u128, b1: MicroB1) -> u128 {
+    let ring = MicroRing::<N>::new(n);
+    let sigma = ring.to_residue(sigma);
+    let u = ring.sub(&ring.mul(&sigma, &sigma), &ring.to_residue(5));
+    let v = ring.add(&sigma, &sigma);
+    let v = ring.add(&v, &v);
+    let u3 = ring.mul(&u, &ring.mul(&u, &u));
+    let v3 = ring.mul(&v, &ring.mul(&v, &v));
+
+    // a24 = (v - u)^3 (3u + v) / (16 u^3 v)
+    let diff = ring.sub(&v, &u);
+    let num = ring.mul(
+        &ring.mul(&diff, &ring.mul(&diff, &diff)),
+        &ring.add(&ring.add(&u, &u), &ring.add(&u, &v)),
+    );
+    let den = ring.mul(&ring.to_residue(16), &ring.mul(&u3, &v));
+    let inv = match invert(ring.to_u128(&den), n) {
+        Ok(inv) => ring.to_residue(inv),
+        Err(g) => return g,
+    };
+    let curve = MicroCurve {
+        a24: ring.mul(&num, &inv),
+        ring,
+    };
+    let ring = &curve.ring;
+
+    // Stage 1
+    let mut q = Point { x: u3, z: v3 };
+    for &k in b1.chain() {
+        q = curve.ladder(&q, k);
+    }
+    let g = ring.gcd(&q.z);
+    if g != 1 {
+        return g;
+    }
+
+    // Stage 2: [mD]q = +-[j]q for some baby step j iff
+    // X_mD Z_j - X_j Z_mD = 0, which catches all primes mD +- j
+    let mut baby = [q; 8];
+    let q2 = curve.double(&q);
+    let mut prev = q;
+    let mut cur = curve.add(&q2, &q, &q);
+    let mut idx = 1;
+    for j in (5..D / 2).step_by(2) {
+        let next = curve.add(&cur, &q2, &prev);
+        prev = cur;
+        cur = next;
+        if BABY_STEPS[idx] == j {
+            baby[idx] = cur;
+            idx += 1;
+        }
+    }
+
+    let giant = curve.ladder(&q, D);
+    let mut g = giant;
+    let mut next = curve.double(&giant);
+    let mut acc = ring.one;
+    for _ in b1.giant_steps() {
+        // g = [mD]q, next = [(m+1)D]q
+        for b in baby.iter() {
+            let t = ring.sub(&ring.mul(&g.x, &b.z), &ring.mul(&b.x, &g.z));
+            acc = ring.mul(&acc, &t);
+        }
+        let after = curve.add(&next, &giant, &g);
+        g = next;
+        next = after;
+    }
+    ring.gcd(&acc)
+}
+
+/// Looks for a factor of an odd composite n < 2^127 with at most
+/// max_curves curves of Suyama's family, all arithmetic being done on
+/// one or two machine words depending on the size of n.
+/// Returns a nontrivial factor of n upon success.
+pub fn micro_ecm(n: u128, b1: MicroB1, max_curves: u32) -> Option<u128> {
+    if n < 4 || n >> 127 != 0 {
+        return None;
+    }
+    if n.is_multiple_of(2) {
+        return Some(2);
+    }
+    for c in 0..max_curves {
+        let sigma = 6 + c as u128;
+        let g = if n >> 64 == 0 {
+            micro_curve::<1>(n, sigma, b1)
+        } else {
+            micro_curve::<2>(n, sigma, b1)
+        };
+        if 1 < g && g < n {
+            return Some(g);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::misc::PrimeTable;
+
+    #[test]
+    fn micro_stage1_chain() {
+        // 2^5 3^3 5^2 7 11 13 17 19 23 29 31 37 41 43 47 does not fit in a u64
+        assert_eq!(CHAIN_47.len(), 2);
+        let product: u128 = CHAIN_47.iter().map(|&k| k as u128).product();
+        assert_eq!(
+            product,
+            32 * 27 * 25 * 7 * 11 * 13 * 17 * 19 * 23 * 29 * 31 * 37 * 41 * 43 * 47
+        );
+    }
+
+    #[test]
+    fn micro_ring_tests() {
+        let n: u128 = (1 << 107) - 1; // prime
+        let ring = MicroRing::<2>::new(n);
+        let a = ring.to_residue(123456789123456789123456789);
+        let b = ring.to_residue(n - 3);
+        assert_eq!(ring.to_u128(&ring.one), 1);
+        assert_eq!(ring.to_u128(&ring.mul(&b, &b)), 9);
+        assert_eq!(ring.to_u128(&ring.add(&a, &b)), 123456789123456789123456786);
+        assert_eq!(
+            ring.to_u128(&ring.sub(&b, &a)),
+            n - 123456789123456789123456792
+        );
+        assert_eq!(invert(4, 7), Ok(2));
+        assert_eq!(invert(6, 15), Err(3));
+    }
+
+    /// Every prime of (B1, B2] is some mD +- j
+    #[test]
+    fn micro_stage2_coverage() {
+        for b1 in [
+            MicroB1::B47,
+            MicroB1::B85,
+            MicroB1::B125,
+            MicroB1::B165,
+            MicroB1::B205,
+        ] {
+            let covered: Vec<u64> = b1
+                .giant_steps()
+                .flat_map(|m| BABY_STEPS.iter().flat_map(move |&j| [m * D - j, m * D + j]))
+                .collect();
+            let table = PrimeTable::new(0..b1.b2() + 1);
+            for p in table.primes_in(b1.b1() + 1..b1.b2() + 1) {
+                assert!(covered.contains(&p), "{:?} misses {}", b1, p);
+            }
+        }
+    }
+
+    #[test]
+    fn micro_ecm_semiprimes() {
+        for &(p, q) in &[
+            (4294967291u128, 4294967279u128),
+            (2147483647, 4294967291),
+            (1000003, 998244353),
+            (1073741789, 2305843009213693951),
+            (4294967291, 2305843009213693951),
+        ] {
+            let n = p * q;
+            let bits = 128 - n.leading_zeros();
+            let f = micro_ecm(n, MicroB1::for_bits(bits), 1000).unwrap();
+            assert!(f == p || f == q);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! The representations of elliptic curves the ECM driver runs on.
+//!
+//! `ecm_with_model` only sees a curve through `CurveModel`: the curve of
+//! a seed, stage 1 by a plan, stage 2, and the coordinate whose gcd with
+//! n reveals a factor. Models can so be compared under the same driver.
+use crate::arith::edwards_point::{EdwardsCurve, EdwardsPoint, Twist};
+use crate::arith::integer::Integer;
+use crate::arith::montgomery_point::{MontgomeryCurve, MontgomeryPoint};
+use crate::arith::poly::PolyRing;
+use crate::arith::ring::ModularRing;
+use crate::parametrization::Parametrization;
+use crate::plan::{Stage1Plan, Stage2Plan};
+
+/// What the ECM driver needs from a curve representation. A model value
+/// is one curve, its points may borrow it.
+pub trait CurveModel<R: ModularRing>: Sized {
+    type Point<'c>
+    where
+        Self: 'c;
+
+    /// The curve of param for the seed sigma over ring, or the gcd with
+    /// n of what could not be inverted
+    fn from_seed(ring: &R, param: Parametrization, sigma: &Integer) -> Result<Self, Integer>;
+
+    /// The starting point of the curve
+    fn start(&self) -> Self::Point<'_>;
+
+    /// p = [k]p for k the product of the largest powers of the primes in
+    /// (done, b1], calling progress with each prime once p has been
+    /// multiplied by its power. Models that multiply by the whole product
+    /// at once only support done = 0, and never call progress.
+    fn stage1<'c>(
+        &'c self,
+        p: &mut Self::Point<'c>,
+        plan: &Stage1Plan,
+        done: u64,
+        progress: &mut dyn FnMut(u64, &Self::Point<'c>),
+    );
+
+    /// Stage 2 on the stage 1 residue q: the gcd of n with the product
+    /// over the pairs of the plan, or with what could not be inverted.
+    /// poly is the polynomial ring of the FFT continuation.
+    fn stage2<'c>(
+        &'c self,
+        q: &Self::Point<'c>,
+        plan: &Stage2Plan,
+        poly: &Option<PolyRing>,
+    ) -> Integer;
+
+    /// A coordinate of p that is zero mod a prime of n when p is the
+    /// neutral element mod that prime
+    fn zero_coordinate<'a>(&self, p: &'a Self::Point<'_>) -> &'a R::Elem;
+
+    /// (X:Z) of p on the Montgomery curve of param and sigma, as save files
+    /// and checkpoints hold it, if the model can give it
+    fn residue(&self, _p: &Self::Point<'_>) -> Option<(Integer, Integer)> {
+        None
+    }
+
+    /// The point of Montgomery coordinates (X:Z), to resume a checkpoint
+    fn resume_point(&self, _x: &Integer, _z: &Integer) -> Option<Self::Point<'_>> {
+        None
+    }
+}
+
+/// x-only arithmetic on the Montgomery curve of the parametrization,
+/// stage 1 by Lucas chains prime by prime. The Edwards families run on
+/// their Montgomery form here, see `EdwardsModel` for their own.
+///
+/// A `MontgomeryCurve` only lives within a closure, so the model keeps
+/// a24 and its points are plain `XZPoint`s, which each call puts back on
+/// the curve.
+pub struct MontgomeryModel<R: ModularRing> {
+    ring: R,
+    a24: R::Elem,
+    x0: R::Elem,
+    z0: R::Elem,
+}
+
+/// The (X:Z) residues of a point of a `MontgomeryModel`
+#[derive(Clone, Debug)]
+pub struct XZPoint<R: ModularRing> {
+    pub x: R::Elem,
+    pub z: R::Elem,
+}
+
+impl<R: ModularRing> XZPoint<R> {
+    fn of(p: &MontgomeryPoint<R>) -> XZPoint<R> {
+        XZPoint {
+            x: p.x.clone(),
+            z: p.z.clone(),
+        }
+    }
+
+    fn on<'c>(&self, curve: &'c MontgomeryCurve<'c, R>) -> MontgomeryPoint<'c, R> {
+        curve.point_from_residues(self.x.clone(), self.z.clone())
+    }
+}
+
+impl<R: ModularRing> MontgomeryModel<R> {
+    pub fn a24(&self) -> &R::Elem {
+        &self.a24
+    }
+
+    /// f of the model's curve
+    fn with_curve<T>(&self, f: impl for<'c> FnOnce(&'c MontgomeryCurve<'c, R>) -> T) -> T {
+        MontgomeryCurve::with(self.a24.clone(), self.ring.clone(), f)
+    }
+}
+
+impl<R: ModularRing> CurveModel<R> for MontgomeryModel<R> {
+    type Point<'c>
+        = XZPoint<R>
+    where
+        R: 'c;
+
+    fn from_seed(ring: &R, param: Parametrization, sigma: &Integer) -> Result<Self, Integer> {
+        let (a24, x0, z0) = param.curve(sigma, ring.modulus())?;
+        Ok(MontgomeryModel {
+            ring: ring.clone(),
+            a24: ring.to_residue(&a24),
+            x0: ring.to_residue(&x0),
+            z0: ring.to_residue(&z0),
+        })
+    }
+
+    fn start(&self) -> XZPoint<R> {
+        XZPoint {
+            x: self.x0.clone(),
+            z: self.z0.clone(),
+        }
+    }
+
+    fn stage1(
+        &self,
+        p: &mut XZPoint<R>,
+        plan: &Stage1Plan,
+        done: u64,
+        progress: &mut dyn FnMut(u64, &XZPoint<R>),
+    ) {
+        *p = self.with_curve(|curve| {
+            let mut q = p.on(curve);
+            if done < 2 {
+                for _ in 0..plan.doublings() {
+                    curve.double_assign(&mut q);
+                }
+            }
+            let mut scratch = [(); 5].map(|_| curve.zero_point());
+            for (chain, e) in plan.chains().iter().filter(|(c, _)| c.k() > done) {
+                for _ in 0..*e {
+                    curve.lucas_chain_assign(&mut q, chain, &mut scratch);
+                }
+                progress(chain.k(), &XZPoint::of(&q));
+            }
+            XZPoint::of(&q)
+        });
+    }
+
+    fn stage2(&self, q: &XZPoint<R>, plan: &Stage2Plan, poly: &Option<PolyRing>) -> Integer {
+        self.with_curve(|curve| crate::run_stage2(curve, &q.on(curve), plan, poly))
+    }
+
+    fn zero_coordinate<'a>(&self, p: &'a XZPoint<R>) -> &'a R::Elem {
+        &p.z
+    }
+
+    fn residue(&self, p: &XZPoint<R>) -> Option<(Integer, Integer)> {
+        Some((self.ring.to_integer(&p.x), self.ring.to_integer(&p.z)))
+    }
+
+    fn resume_point(&self, x: &Integer, z: &Integer) -> Option<XZPoint<R>> {
+        Some(XZPoint {
+            x: self.ring.to_residue(x),
+            z: self.ring.to_residue(z),
+        })
+    }
+}
+
+/// Extended coordinates on the twisted Edwards curve of an Edwards
+/// family, stage 1 in one scalar multiplication by the product of the
+/// prime powers up to b1. Stage 2 runs on the equivalent Montgomery curve,
+/// to which the stage 1 residue is mapped.
+///
+/// Its points have no Montgomery residue to save or checkpoint: the
+/// Edwards families have no GMP-ECM param number to resume them with.
+pub struct EdwardsModel<R: ModularRing> {
+    curve: EdwardsCurve<R>,
+    p0: EdwardsPoint<R>,
+    /// a24 of the Montgomery form of the curve
+    a24: R::Elem,
+}
+
+impl<R: ModularRing> CurveModel<R> for EdwardsModel<R> {
+    type Point<'c>
+        = EdwardsPoint<R>
+    where
+        R: 'c;
+
+    /// Panics if param is not an Edwards family
+    fn from_seed(ring: &R, param: Parametrization, sigma: &Integer) -> Result<Self, Integer> {
+        let torsion = match param {
+            Parametrization::Edwards(torsion) => torsion,
+            _ => panic!("{} has no Edwards curves", param),
+        };
+        let (d, x, y) = torsion.curve(sigma, ring.modulus())?;
+        let curve = EdwardsCurve::with_ring(Twist::One, ring.to_residue(&d), ring.clone());
+        let a24 = curve.montgomery_a24()?;
+        let p0 = curve.point(&x, &y);
+        Ok(EdwardsModel { curve, p0, a24 })
+    }
+
+    fn start(&self) -> EdwardsPoint<R> {
+        self.p0.clone()
+    }
+
+    fn stage1(
+        &self,
+        p: &mut EdwardsPoint<R>,
+        plan: &Stage1Plan,
+        done: u64,
+        _progress: &mut dyn FnMut(u64, &EdwardsPoint<R>),
+    ) {
+        assert_eq!(done, 0, "Edwards curves multiply by the whole product");
+        *p = self.curve.mul(p, plan.scalar());
+    }
+
+    fn stage2(&self, q: &EdwardsPoint<R>, plan: &Stage2Plan, poly: &Option<PolyRing>) -> Integer {
+        let ring = self.curve.ring().clone();
+        MontgomeryCurve::with(self.a24.clone(), ring, |curve| {
+            crate::run_stage2(curve, &q.to_montgomery(curve), plan, poly)
+        })
+    }
+
+    /// X, which is zero for the neutral element (0, 1) and the point
+    /// (0, -1) of order 2
+    fn zero_coordinate<'a>(&self, p: &'a EdwardsPoint<R>) -> &'a R::Elem {
+        &p.x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::ring::DefaultRing;
+    use crate::parametrization::Torsion;
+
+    /// Stage 1 multiplies the starting point by the product of the prime
+    /// powers up to b1, prime by prime, and reports each prime on the way
+    #[test]
+    fn montgomery_model_stage1() {
+        let n = Integer::from(1000003u64 * 999983);
+        let ring = DefaultRing::new(&n).unwrap();
+        let plan = Stage1Plan::new(200);
+        let params = [
+            Parametrization::Suyama,
+            Parametrization::Torsion12,
+            Parametrization::Edwards(Torsion::Z12),
+            Parametrization::Edwards(Torsion::Z2xZ8),
+        ];
+        for param in params.iter() {
+            let model = MontgomeryModel::from_seed(&ring, *param, &Integer::from(7)).unwrap();
+            let mut q = model.start();
+            let mut primes = vec![];
+            model.stage1(&mut q, &plan, 0, &mut |prime, _| primes.push(prime));
+            let expected = model.with_curve(|curve| {
+                let start = model.start().on(curve);
+                XZPoint::of(&curve.montgomery_ladder(&start, plan.scalar()))
+            });
+            // X/Z may not exist: for Z2xZ8 the order mod 999983 divides k
+            let (x, z) = (ring.mul(&q.x, &expected.z), ring.mul(&expected.x, &q.z));
+            assert_eq!(x, z, "{}", param);
+            assert_eq!(primes.len(), plan.chains().len());
+            assert_eq!(model.zero_coordinate(&q), &q.z);
+        }
+    }
+
+    /// The Edwards model's stage 1 lands, mapped to the Montgomery form,
+    /// on the point the Montgomery model gets to on the same curve
+    #[test]
+    fn edwards_model_stage1() {
+        let n = Integer::from(1000003u64 * 999983);
+        let ring = DefaultRing::new(&n).unwrap();
+        let plan = Stage1Plan::new(200);
+        for torsion in [Torsion::Z12, Torsion::Z2xZ8] {
+            let param = Parametrization::Edwards(torsion);
+            let sigma = Integer::from(7);
+            let edwards = EdwardsModel::from_seed(&ring, param, &sigma).unwrap();
+            let montgomery = MontgomeryModel::from_seed(&ring, param, &sigma).unwrap();
+            assert_eq!(&edwards.a24, montgomery.a24());
+            let mut primes = vec![];
+            let mut p = edwards.start();
+            edwards.stage1(&mut p, &plan, 0, &mut |prime, _| primes.push(prime));
+            assert!(primes.is_empty());
+            let p = montgomery.with_curve(|curve| XZPoint::of(&p.to_montgomery(curve)));
+            let mut q = montgomery.start();
+            montgomery.stage1(&mut q, &plan, 0, &mut |_, _| {});
+            let (x, z) = (ring.mul(&q.x, &p.z), ring.mul(&p.x, &q.z));
+            assert_eq!(x, z, "{}", param);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+//! The curves ECM tries, each given by a parameter sigma, numbered as
+//! GMP-ECM's `-param` so that (param, sigma) names the same curve in
+//! both programs, and the Edwards curves with large torsion GMP-ECM
+//! selects by `-torsion`.
+use crate::arith::integer::{Complete, Integer, RandState};
+use crate::arith::misc::randint;
+use crate::arith::modular_arithmetic::*;
+use std::fmt;
+
+/// How a Montgomery curve and its starting point are derived from sigma
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Parametrization {
+    /// Param 0, Suyama's: u = sigma^2 - 5, v = 4 sigma,
+    /// x0 = u^3 / v^3, A = (v - u)^3 (3u + v) / (4 u^3 v) - 2.
+    /// The curves have a rational torsion subgroup Z/6.
+    Suyama,
+    /// Param 1: a24 = sigma^2 / 2^64, x0 = 2. In Montgomery representation
+    /// with 64-bit limbs a24 is the single limb sigma^2, for sigma < 2^32.
+    BatchSquare,
+    /// Param 2, Montgomery's curves with rational torsion Z/12: (u, v) =
+    /// sigma (-2, 4) on v^2 = u^3 - 12u, t = v / 2u,
+    /// a = (t^2 - 1) / (t^2 + 3), x0 = (3a^2 + 1) / 4a,
+    /// A = -(3a^4 + 6a^2 - 1) / 4a^3.
+    Torsion12,
+    /// Param 3: a24 = sigma / 2^32, x0 = 2
+    Batch32,
+    /// An Edwards curve of one of the torsion families, on which stage 1
+    /// runs in Edwards form before stage 2 goes on in Montgomery form.
+    /// GMP-ECM gives these no param number.
+    Edwards(Torsion),
+}
+
+/// Families of Edwards curves x^2 + y^2 = 1 + dx^2y^2 with a large
+/// rational torsion subgroup, from Bernstein, Birkner, Lange and Peters,
+/// "ECM using Edwards curves" (2013). Each is parametrized by a point of
+/// infinite order on an elliptic curve of rank 1, sigma times which gives
+/// d and a point of infinite order on the Edwards curve.
+///
+/// Their group orders modulo primes are divisible by 12 and 16. There is
+/// no Z/16 family: that torsion is not possible over Q by Mazur's theorem.
+/// Barbulescu et al.'s subfamilies of Z/2 x Z/8, whose curves gain a
+/// point of order 16 modulo some primes, are not implemented, and "Z16"
+/// does not parse.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Torsion {
+    /// Torsion Z/12: (u, v) = sigma (-2, 4) on v^2 = u^3 - 12u gives the
+    /// Montgomery curve of param 2, which with B = A + 2 has the point of
+    /// x = (3a^2 + 1) / 4a and y = (a + 1)(u^2 + 12) / 16av
+    Z12,
+    /// Torsion Z/2 x Z/8: (X, Y) = sigma (-2, 4) on Y^2 = X^3 + 2X^2 - 8X,
+    /// u = -(X + Y + 4) / 2(X + 1), w = (u^2 + 2u + 2) / (u^2 - 2),
+    /// d = (2w^2 - 1) / w^4 and the point (w^2, (u^2 + u - X/2) / (u^2 - 2))
+    Z2xZ8,
+}
+
+impl Parametrization {
+    /// The parametrization GMP-ECM numbers param
+    pub fn from_param(param: u32) -> Option<Parametrization> {
+        match param {
+            0 => Some(Parametrization::Suyama),
+            1 => Some(Parametrization::BatchSquare),
+            2 => Some(Parametrization::Torsion12),
+            3 => Some(Parametrization::Batch32),
+            _ => None,
+        }
+    }
+
+    /// GMP-ECM's number for the parametrization, if it has one
+    pub fn param(self) -> Option<u32> {
+        match self {
+            Parametrization::Suyama => Some(0),
+            Parametrization::BatchSquare => Some(1),
+            Parametrization::Torsion12 => Some(2),
+            Parametrization::Batch32 => Some(3),
+            Parametrization::Edwards(_) => None,
+        }
+    }
+
+    /// A random sigma in the range GMP-ECM draws it from
+    pub fn random_sigma(self, rand: &mut RandState, n: &Integer) -> Integer {
+        match self {
+            Parametrization::Suyama => randint(rand, &Integer::from(6), &(n - 1u32).complete()),
+            _ => randint(rand, &Integer::from(2), &Integer::from(u32::MAX)),
+        }
+    }
+
+    /// a24 = (A + 2)/4 and the starting point (X0:Z0) of the curve for
+    /// sigma mod n, or the gcd with n of what could not be inverted. For
+    /// the Edwards families, the Montgomery form of the curve, a24 =
+    /// 1/(1 - d) and (X0:Z0) = (1 + y : 1 - y).
+    pub fn curve(
+        self,
+        sigma: &Integer,
+        n: &Integer,
+    ) -> Result<(Integer, Integer, Integer), Integer> {
+        let invert = |a: &Integer| invert_mod(a, n).ok_or_else(|| a.gcd_ref(n).complete());
+        match self {
+            Parametrization::Suyama => {
+                let v = multiply_mod(&Integer::from(4), sigma, n);
+                let u = subtract_mod(&Integer::from(sigma).square(), &Integer::from(5), n);
+
+                let diff = subtract_mod(&v, &u, n);
+                let u_cubed = pow_mod(&u, 3, n);
+
+                // if 4u^3v is not invertible mod n then by Bezout its
+                // GCD with n is > 1
+                let inv = invert(&(4 * (&u_cubed * &v).complete()))?;
+                // c determines the curve in Montgomery form y^2 = x^3 + cx^2 + x
+                let c = take_mod(
+                    &((pow_mod(&diff, 3, n) * (3u32 * &u + &v).complete() * &inv) - 2),
+                    n,
+                );
+                let a24 = multiply_mod(&(&c + 2u32).complete(), &invert(&Integer::from(4))?, n);
+                // Initial point in Montgomery form [X:Z]=[u^3 mod n : v^3 mod n]
+                Ok((a24, u_cubed, pow_mod(&v, 3, n)))
+            }
+            Parametrization::BatchSquare => {
+                let r = invert(&(Integer::from(1) << 64))?;
+                let a24 = multiply_mod(&take_mod(&sigma.square_ref().complete(), n), &r, n);
+                Ok((a24, take_mod(&Integer::from(2), n), Integer::from(1)))
+            }
+            Parametrization::Batch32 => {
+                let r = invert(&(Integer::from(1) << 32))?;
+                let a24 = multiply_mod(&take_mod(sigma, n), &r, n);
+                Ok((a24, take_mod(&Integer::from(2), n), Integer::from(1)))
+            }
+            Parametrization::Torsion12 => {
+                let (a24, x0, _) = torsion12(sigma, n)?;
+                Ok((a24, x0, Integer::from(1)))
+            }
+            Parametrization::Edwards(torsion) => {
+                let (d, _, y) = torsion.curve(sigma, n)?;
+                let a24 = invert(&subtract_mod(&Integer::from(1), &d, n))?;
+                let x0 = take_mod(&(Integer::from(1) + &y), n);
+                Ok((a24, x0, subtract_mod(&Integer::from(1), &y, n)))
+            }
+        }
+    }
+}
+
+impl Torsion {
+    /// d and the starting point (x0, y0) of the curve for sigma mod n, or
+    /// the gcd with n of what could not be inverted
+    pub fn curve(
+        self,
+        sigma: &Integer,
+        n: &Integer,
+    ) -> Result<(Integer, Integer, Integer), Integer> {
+        let invert = |a: &Integer| invert_mod(a, n).ok_or_else(|| a.gcd_ref(n).complete());
+        let one = Integer::from(1);
+        match self {
+            Torsion::Z12 => {
+                let (a24, x, y) = torsion12(sigma, n)?;
+                // d = (a24 - 1)/a24, and x0 = x/y, y0 = (x - 1)/(x + 1)
+                let d = multiply_mod(&subtract_mod(&a24, &one, n), &invert(&a24)?, n);
+                let x0 = multiply_mod(&x, &invert(&y)?, n);
+                let y0 = multiply_mod(
+                    &subtract_mod(&x, &one, n),
+                    &invert(&take_mod(&(Integer::from(&x) + 1), n))?,
+                    n,
+                );
+                Ok((d, x0, y0))
+            }
+            Torsion::Z2xZ8 => {
+                let p = (take_mod(&Integer::from(-2), n), Integer::from(4));
+                let (x, y) = weierstrass_mul(sigma, &p, &Integer::from(2), &Integer::from(-8), n)?;
+                // u = -(X + Y + 4)/2(X + 1)
+                let num = take_mod(&(Integer::from(-4) - &x - &y), n);
+                let u = multiply_mod(&num, &invert(&(2 * (&x + 1u32).complete()))?, n);
+                let u2 = multiply_mod(&u, &u, n);
+                let den = invert(&subtract_mod(&u2, &Integer::from(2), n))?;
+                // w = (u^2 + 2u + 2)/(u^2 - 2), d = (2w^2 - 1)/w^4
+                let w = multiply_mod(&take_mod(&(&u2 + 2 * u.clone() + 2), n), &den, n);
+                let w2 = multiply_mod(&w, &w, n);
+                let w4 = multiply_mod(&w2, &w2, n);
+                let d = multiply_mod(&take_mod(&(2 * w2.clone() - 1), n), &invert(&w4)?, n);
+                // y0 = (u^2 + u - X/2)/(u^2 - 2)
+                let half_x = multiply_mod(&x, &invert(&Integer::from(2))?, n);
+                let y0 = multiply_mod(&subtract_mod(&take_mod(&(u2 + u), n), &half_x, n), &den, n);
+                Ok((d, w2, y0))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Torsion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Torsion::Z12 => write!(f, "Z12"),
+            Torsion::Z2xZ8 => write!(f, "Z2xZ8"),
+        }
+    }
+}
+
+impl std::str::FromStr for Torsion {
+    type Err = String;
+
+    /// The names of GMP-ECM's `-torsion`
+    fn from_str(s: &str) -> Result<Torsion, String> {
+        match s {
+            "Z12" | "z12" => Ok(Torsion::Z12),
+            "Z2xZ8" | "z2xz8" => Ok(Torsion::Z2xZ8),
+            _ => Err(format!("Unknown torsion {}", s)),
+        }
+    }
+}
+
+/// The curve of param 2 for sigma: a24, the abscissa of its starting
+/// point, and the ordinate of that point on By^2 = x^3 + Ax^2 + x with
+/// B = A + 2, i.e. 4a24 y^2 = ..., which the Edwards form needs
+fn torsion12(sigma: &Integer, n: &Integer) -> Result<(Integer, Integer, Integer), Integer> {
+    let invert = |a: &Integer| invert_mod(a, n).ok_or_else(|| a.gcd_ref(n).complete());
+    let p = (take_mod(&Integer::from(-2), n), Integer::from(4));
+    let (u, v) = weierstrass_mul(sigma, &p, &Integer::from(0), &Integer::from(-12), n)?;
+    let t = multiply_mod(&v, &invert(&(2 * u.clone()))?, n);
+    let t2 = multiply_mod(&t, &t, n);
+    let a = multiply_mod(&(&t2 - 1u32).complete(), &invert(&(t2 + 3))?, n);
+    let a2 = multiply_mod(&a, &a, n);
+    let a3 = multiply_mod(&a2, &a, n);
+    let a4 = multiply_mod(&a2, &a2, n);
+    // x0 = (3a^2 + 1)/4a
+    let x0 = multiply_mod(
+        &((3u32 * &a2).complete() + 1),
+        &invert(&(4 * a.clone()))?,
+        n,
+    );
+    // A + 2 = (-3a^4 - 6a^2 + 1 + 8a^3)/4a^3, a24 = (A + 2)/4
+    let num = take_mod(&(8 * a3.clone() - 3 * a4 - 6 * a2 + 1), n);
+    let a24 = multiply_mod(&num, &invert(&(16 * a3))?, n);
+    // y0 = (a + 1)(u^2 + 12)/16av
+    let num = multiply_mod(
+        &(a.clone() + 1),
+        &take_mod(&(u.square_ref().complete() + 12), n),
+        n,
+    );
+    let y0 = multiply_mod(&num, &invert(&(16 * multiply_mod(&a, &v, n)))?, n);
+    Ok((a24, x0, y0))
+}
+
+impl fmt::Display for Parametrization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self, self.param()) {
+            (Parametrization::Edwards(torsion), _) => write!(f, "{}", torsion),
+            (_, param) => write!(f, "{}", param.unwrap()),
+        }
+    }
+}
+
+/// [k]P on y^2 = x^3 + a2 x^2 + a4 x mod n in affine coordinates, k >= 1,
+/// by double and add. Returns the gcd with n of a denominator that could
+/// not be inverted, which also covers reaching the point at infinity.
+fn weierstrass_mul(
+    k: &Integer,
+    p: &(Integer, Integer),
+    a2: &Integer,
+    a4: &Integer,
+    n: &Integer,
+) -> Result<(Integer, Integer), Integer> {
+    let add = |p: &(Integer, Integer), q: &(Integer, Integer)| {
+        let (num, den) = if p == q {
+            // (3x^2 + 2 a2 x + a4) / 2y
+            let x2 = multiply_mod(&p.0, &p.0, n);
+            let num = 3 * x2 + 2 * multiply_mod(a2, &p.0, n) + a4;
+            (take_mod(&num, n), take_mod(&(2 * p.1.clone()), n))
+        } else {
+            (subtract_mod(&q.1, &p.1, n), subtract_mod(&q.0, &p.0, n))
+        };
+        let inv = invert_mod(&den, n).ok_or_else(|| den.gcd(n))?;
+        let l = multiply_mod(&num, &inv, n);
+        let x = subtract_mod(&multiply_mod(&l, &l, n), a2, n);
+        let x = subtract_mod(&subtract_mod(&x, &p.0, n), &q.0, n);
+        let y = subtract_mod(&multiply_mod(&l, &subtract_mod(&p.0, &x, n), n), &p.1, n);
+        Ok::<_, Integer>((x, y))
+    };
+    assert!(*k >= 1, "k must be positive");
+    let mut q = p.clone();
+    for i in (0..k.significant_bits() - 1).rev() {
+        q = add(&q, &q)?;
+        if k.get_bit(i) {
+            q = add(&q, p)?;
+        }
+    }
+    Ok(q)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parametrization_tests() {
+        let n = Integer::from(1000003u64 * 999983);
+        for param in 0..4 {
+            let parametrization = Parametrization::from_param(param).unwrap();
+            assert_eq!(parametrization.param(), Some(param));
+        }
+        assert_eq!(Parametrization::from_param(4), None);
+
+        // a24 is sigma^2 / 2^64 and sigma / 2^32
+        let sigma = Integer::from(123456789);
+        let (a24, x0, z0) = Parametrization::BatchSquare.curve(&sigma, &n).unwrap();
+        assert_eq!((x0, z0), (Integer::from(2), Integer::from(1)));
+        let square = sigma.square_ref().complete() % &n;
+        assert_eq!(multiply_mod(&a24, &(Integer::from(1) << 64), &n), square);
+        let (a24, _, _) = Parametrization::Batch32.curve(&sigma, &n).unwrap();
+        assert_eq!(multiply_mod(&a24, &(Integer::from(1) << 32), &n), sigma);
+
+        // 3 sigma (-2, 4) = sigma (-2, 4) + 2 sigma (-2, 4)
+        let p = ((&n - 2u32).complete(), Integer::from(4));
+        let (a2, a) = (Integer::from(0), Integer::from(-12));
+        let s = weierstrass_mul(&sigma, &p, &a2, &a, &n).unwrap();
+        let s2 = weierstrass_mul(&(2u32 * &sigma).complete(), &p, &a2, &a, &n).unwrap();
+        let s3 = weierstrass_mul(&(3u32 * &sigma).complete(), &p, &a2, &a, &n).unwrap();
+        let x = take_mod(
+            &(multiply_mod(&s.0, &s.0, &n) * &s.0 - 12 * s.0.clone()),
+            &n,
+        );
+        assert_eq!(multiply_mod(&s.1, &s.1, &n), x);
+        assert_ne!(s3, s2);
+        // a denominator divisible by 1000003 shows up as a factor: 2P
+        // on a curve mod 1000003 * 999983 where P has order 2 mod 1000003
+        let p = Integer::from(1000003);
+        let g = weierstrass_mul(
+            &Integer::from(2),
+            &(Integer::from(0), multiply_mod(&p, &Integer::from(7), &n)),
+            &a2,
+            &a,
+            &n,
+        );
+        assert_eq!(g, Err(p));
+    }
+
+    #[test]
+    fn random_sigma_tests() {
+        let mut rand = RandState::new();
+        let n = Integer::from(1000003u64 * 999983);
+        for _ in 0..1000 {
+            let sigma = Parametrization::Suyama.random_sigma(&mut rand, &n);
+            assert!(6 <= sigma && sigma < n, "{}", sigma);
+            let sigma = Parametrization::Batch32.random_sigma(&mut rand, &n);
+            assert!(2 <= sigma && sigma <= u32::MAX, "{}", sigma);
+        }
+        // both ends of [6, n - 1] are drawn
+        let n = Integer::from(11);
+        let drawn: Vec<_> = (0..200)
+            .map(|_| Parametrization::Suyama.random_sigma(&mut rand, &n))
+            .collect();
+        for sigma in 6..11 {
+            assert!(drawn.contains(&Integer::from(sigma)), "{}", sigma);
+        }
+    }
+
+    #[test]
+    fn torsion_tests() {
+        let n = Integer::from(1000003u64 * 999983);
+        for torsion in [Torsion::Z12, Torsion::Z2xZ8] {
+            assert_eq!(torsion.to_string().parse(), Ok(torsion));
+            for sigma in 2..20 {
+                // x0^2 + y0^2 = 1 + d x0^2 y0^2
+                let (d, x, y) = torsion.curve(&Integer::from(sigma), &n).unwrap();
+                let (x2, y2) = (multiply_mod(&x, &x, &n), multiply_mod(&y, &y, &n));
+                let rhs = take_mod(&(multiply_mod(&d, &multiply_mod(&x2, &y2, &n), &n) + 1), &n);
+                assert_eq!(take_mod(&(x2 + y2), &n), rhs, "{} sigma={}", torsion, sigma);
+            }
+        }
+        assert!("Z16".parse::<Torsion>().is_err());
+        let edwards = Parametrization::Edwards(Torsion::Z2xZ8);
+        assert_eq!(
+            (edwards.param(), edwards.to_string()),
+            (None, "Z2xZ8".to_string())
+        );
+    }
+}
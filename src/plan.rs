@@ -0,0 +1,634 @@
+//! What stage 1 and stage 2 compute for given bounds, independently of
+//! the number being factored and of the curve.
+//!
+//! Plans are immutable: build them once per (B1, B2), wrap them in an
+//! `Arc`, and share them across curves, threads and numbers.
+use crate::arith::integer::{Complete, Integer, Pow};
+use crate::arith::misc::PrimeTable;
+use crate::arith::montgomery_point::FULL_ADD;
+use crate::arith::prac::{LucasChain, ADD, DUP};
+use std::ops::Range;
+use std::sync::OnceLock;
+
+/// Size of the ranges sieved at once for the stage 2 pairs
+const STAGE2_WINDOW: u64 = 1 << 20;
+
+/// Stage 1 for a bound b1: `doublings` doublings for the largest power
+/// of 2 up to b1, then for each odd prime p <= b1 the Lucas chain for p,
+/// as many times as p divides the largest power of p up to b1.
+///
+/// The plan also provides the product of these prime powers, for the
+/// curve models that multiply by it in one go rather than prime by prime.
+/// It is only built the first time one asks for it: at large b1 it takes
+/// about 1.44 b1 bits, which the prime by prime models never need.
+#[derive(Debug)]
+pub struct Stage1Plan {
+    b1: u64,
+    doublings: u32,
+    chains: Vec<(LucasChain, u32)>,
+    scalar: OnceLock<Integer>,
+}
+
+impl Stage1Plan {
+    pub fn new(b1: u64) -> Stage1Plan {
+        let chains = PrimeTable::new(0..b1 + 1)
+            .primes_in(3..b1 + 1)
+            .map(|p| {
+                let mut e = 1;
+                let mut q = p;
+                while q <= b1 / p {
+                    q *= p;
+                    e += 1;
+                }
+                (LucasChain::new(p), e)
+            })
+            .collect::<Vec<_>>();
+        let doublings = if b1 < 2 { 0 } else { b1.ilog2() };
+        Stage1Plan {
+            b1,
+            doublings,
+            chains,
+            scalar: OnceLock::new(),
+        }
+    }
+
+    pub fn b1(&self) -> u64 {
+        self.b1
+    }
+
+    pub fn doublings(&self) -> u32 {
+        self.doublings
+    }
+
+    /// Chains for the odd primes up to b1 in increasing order, with the
+    /// number of times each is to be applied
+    pub fn chains(&self) -> &[(LucasChain, u32)] {
+        &self.chains
+    }
+
+    /// The product of the largest prime powers up to b1, built on the
+    /// first call
+    pub fn scalar(&self) -> &Integer {
+        self.scalar.get_or_init(|| {
+            let mut powers: Vec<Integer> = self
+                .chains
+                .iter()
+                .map(|(chain, e)| Integer::from(chain.k()).pow(*e))
+                .collect();
+            powers.push(Integer::from(1) << self.doublings);
+            product(&powers)
+        })
+    }
+
+    /// Number of multiplications stage 1 takes
+    pub fn cost(&self) -> u64 {
+        DUP * self.doublings as u64
+            + self
+                .chains
+                .iter()
+                .map(|(chain, e)| chain.cost() * *e as u64)
+                .sum::<u64>()
+    }
+}
+
+/// Product of values, by a product tree so that the large multiplications
+/// are balanced
+fn product(values: &[Integer]) -> Integer {
+    match values {
+        [] => Integer::from(1),
+        [v] => v.clone(),
+        _ => {
+            let (low, high) = values.split_at(values.len() / 2);
+            product(low) * product(high)
+        }
+    }
+}
+
+/// Default bound on the number of baby steps a stage 2 plan keeps
+pub const DEFAULT_MAX_BABY_STEPS: usize = 1 << 16;
+
+/// Primorials whose multiples are tried as d, each up to the next one
+const WHEELS: [u64; 6] = [6, 30, 210, 2310, 30030, 510510];
+
+/// How stage 2 goes through the pairs (g, j) of giant and baby steps
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Continuation {
+    /// One multiplication for each pair that covers a prime
+    Standard,
+    /// All the pairs at once by polynomial arithmetic: the product over
+    /// the giant steps g of F(x_gd), F having the x_j as roots
+    Fft,
+}
+
+/// Polynomial f of the Brent–Suyama extension, of degree e >= 1.
+///
+/// Stage 2 then compares [f(gd)]Q with [f(j)]Q rather than [gd]Q with
+/// [j]Q. Since f(-x) = +-f(x), one of f(gd) - f(j) and f(gd) + f(j) is
+/// still divisible by gd - j and the other by gd + j, and their other
+/// factors catch some orders whose largest prime lies above b2.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SuyamaPolynomial {
+    /// x^e
+    Power(u32),
+    /// The Dickson polynomial D_e(x, -1), which has more algebraic
+    /// factors in f(x) -+ f(y) than x^e
+    Dickson(u32),
+}
+
+impl SuyamaPolynomial {
+    pub fn degree(&self) -> u32 {
+        match *self {
+            SuyamaPolynomial::Power(e) | SuyamaPolynomial::Dickson(e) => e,
+        }
+    }
+
+    pub fn eval(&self, x: u64) -> Integer {
+        match *self {
+            SuyamaPolynomial::Power(e) => Integer::from(x).pow(e),
+            SuyamaPolynomial::Dickson(e) => {
+                // D_0 = 2, D_1 = x, D_k = x D_(k-1) + D_(k-2)
+                let (mut prev, mut cur) = (Integer::from(2), Integer::from(x));
+                if e == 0 {
+                    return prev;
+                }
+                for _ in 1..e {
+                    let next = (&cur * x).complete() + &prev;
+                    prev = std::mem::replace(&mut cur, next);
+                }
+                cur
+            }
+        }
+    }
+
+    /// The finite differences Delta^k f(x0) of step h, for k = 0..=e:
+    /// adding each one to the previous moves f(x) to f(x + h), the last
+    /// one being constant
+    pub fn differences(&self, x0: u64, h: u64) -> Vec<Integer> {
+        let e = self.degree() as usize;
+        let mut table: Vec<Integer> = (0..=e as u64).map(|k| self.eval(x0 + k * h)).collect();
+        for level in 1..=e {
+            for k in (level..=e).rev() {
+                let (lo, hi) = table.split_at_mut(k);
+                hi[0] -= &lo[k - 1];
+            }
+        }
+        table
+    }
+}
+
+/// Stage 2 over [b2min, b2] by Montgomery's improved standard
+/// continuation, or its FFT variant. After stage 1 to b1, b2min is
+/// usually b1 + 1; a larger one extends an earlier stage 2 that went up to
+/// b2min - 1, or splits a curve's stage 2 into ranges.
+///
+/// Each prime p in [b2min, b2] is written p = gd + j or p = gd - j with
+/// 0 < j < d/2 coprime to d, for d a multiple of a primorial. Stage 2
+/// keeps the baby steps [j]Q, walks the giant steps [gd]Q, and one
+/// multiplication by x_gd - x_j covers both gd + j and gd - j, the points
+/// being normalized to Z = 1 by blocks with one inversion each.
+///
+/// For the standard continuation, stage 2 sieves the pairs (g, j) for
+/// which at least one of gd -+ j is prime as the giant steps reach them,
+/// one `PairWindow` of about `STAGE2_WINDOW` integers at a time, so the
+/// plan itself keeps only d, the baby steps and the bounds. The FFT
+/// continuation goes through all the pairs and needs no sieving, for b2
+/// far beyond what the pair by pair loop allows.
+#[derive(Debug)]
+pub struct Stage2Plan {
+    b2min: u64,
+    b2: u64,
+    continuation: Continuation,
+    d: u64,
+    /// The j coprime to d in (0, d/2), in increasing order
+    baby_steps: Vec<u64>,
+    first_giant_step: u64,
+    giant_steps: usize,
+    suyama: Option<SuyamaPolynomial>,
+}
+
+impl Stage2Plan {
+    /// Plan for the primes in (b1, b2]
+    pub fn new(b1: u64, b2: u64) -> Stage2Plan {
+        Stage2Plan::with_max_baby_steps(b1, b2, DEFAULT_MAX_BABY_STEPS)
+    }
+
+    /// Plan keeping at most max_baby_steps baby steps (each one point and
+    /// one residue) in memory during stage 2
+    pub fn with_max_baby_steps(b1: u64, b2: u64, max_baby_steps: usize) -> Stage2Plan {
+        Stage2Plan::range_with_max_baby_steps(Continuation::Standard, b1 + 1, b2, max_baby_steps)
+    }
+
+    /// Plan for the FFT continuation
+    pub fn fft(b1: u64, b2: u64) -> Stage2Plan {
+        Stage2Plan::fft_with_max_baby_steps(b1, b2, DEFAULT_MAX_BABY_STEPS)
+    }
+
+    /// Plan for the FFT continuation, with the degree of the polynomials
+    /// it multiplies bounded by max_baby_steps
+    pub fn fft_with_max_baby_steps(b1: u64, b2: u64, max_baby_steps: usize) -> Stage2Plan {
+        Stage2Plan::range_with_max_baby_steps(Continuation::Fft, b1 + 1, b2, max_baby_steps)
+    }
+
+    /// Plan for the primes in [b2min, b2]
+    pub fn range(continuation: Continuation, b2min: u64, b2: u64) -> Stage2Plan {
+        Stage2Plan::range_with_max_baby_steps(continuation, b2min, b2, DEFAULT_MAX_BABY_STEPS)
+    }
+
+    pub fn range_with_max_baby_steps(
+        continuation: Continuation,
+        b2min: u64,
+        b2: u64,
+        max_baby_steps: usize,
+    ) -> Stage2Plan {
+        // 2 is left to stage 1, and the first giant step is then >= 1
+        let b2min = b2min.max(3);
+        let d = match continuation {
+            Continuation::Standard => choose_d(b2min, b2, max_baby_steps, overhead),
+            Continuation::Fft => choose_d(b2min, b2, max_baby_steps, fft_overhead),
+        };
+        let half = d / 2;
+        // p belongs to giant step g when gd - d/2 <= p < gd + d/2
+        let giant_step = |p: u64| (p + half) / d;
+        let first_giant_step = giant_step(b2min);
+        let giant_steps = if b2 >= b2min {
+            (giant_step(b2) - first_giant_step + 1) as usize
+        } else {
+            0
+        };
+        Stage2Plan {
+            b2min,
+            b2,
+            continuation,
+            d,
+            baby_steps: (1..half).filter(|&j| gcd(j, d) == 1).collect(),
+            first_giant_step,
+            giant_steps,
+            suyama: None,
+        }
+    }
+
+    /// The same plan, with the Brent–Suyama extension by f
+    pub fn with_brent_suyama(mut self, f: SuyamaPolynomial) -> Stage2Plan {
+        assert!(f.degree() >= 1, "Brent–Suyama needs a degree >= 1");
+        self.suyama = Some(f);
+        self
+    }
+
+    pub fn b2min(&self) -> u64 {
+        self.b2min
+    }
+
+    pub fn brent_suyama(&self) -> Option<SuyamaPolynomial> {
+        self.suyama
+    }
+
+    pub fn b2(&self) -> u64 {
+        self.b2
+    }
+
+    pub fn continuation(&self) -> Continuation {
+        self.continuation
+    }
+
+    pub fn d(&self) -> u64 {
+        self.d
+    }
+
+    pub fn baby_steps(&self) -> &[u64] {
+        &self.baby_steps
+    }
+
+    /// g such that the first giant step is [gd]Q, at least 1
+    pub fn first_giant_step(&self) -> u64 {
+        self.first_giant_step
+    }
+
+    pub fn giant_steps(&self) -> usize {
+        self.giant_steps
+    }
+
+    /// Number of giant steps in each `PairWindow`
+    pub fn window_steps(&self) -> usize {
+        (STAGE2_WINDOW / self.d).max(1) as usize
+    }
+
+    /// Sieves the pairs of the giant steps [step, step + window_steps()),
+    /// counted from the first one, i.e. the primes of
+    /// [gd - d/2, (g + window_steps())d - d/2) within [b2min, b2]
+    /// (standard continuation)
+    pub fn pair_window(&self, step: usize) -> PairWindow {
+        let (d, half) = (self.d, self.d / 2);
+        let steps = step..self.giant_steps.min(step + self.window_steps());
+        let words = self.baby_steps.len().div_ceil(64);
+        let mut bitmaps = vec![0u64; steps.len() * words];
+        let g = self.first_giant_step + step as u64;
+        let lo = (g * d - half).max(self.b2min);
+        let hi = ((g + steps.len() as u64) * d - half).min(self.b2 + 1);
+        let table = PrimeTable::new(lo..hi);
+        // primes dividing d are < d/2 <= b2min, except for tiny b2min
+        // where they are left to stage 1
+        for p in table.primes_in(lo..hi).filter(|p| !d.is_multiple_of(*p)) {
+            let g = (p + half) / d;
+            let i = self.baby_steps.binary_search(&(g * d).abs_diff(p)).unwrap();
+            let k = (g - self.first_giant_step) as usize - steps.start;
+            bitmaps[k * words + i / 64] |= 1 << (i % 64);
+        }
+        PairWindow {
+            steps,
+            words,
+            bitmaps,
+        }
+    }
+
+    /// The pair windows of all the giant steps, in order
+    pub fn pair_windows(&self) -> impl Iterator<Item = PairWindow> + '_ {
+        (0..self.giant_steps)
+            .step_by(self.window_steps())
+            .map(move |step| self.pair_window(step))
+    }
+
+    /// Number of multiplications stage 2 takes, leaving out the ladders
+    /// to the first giant steps (an estimate for the FFT continuation).
+    /// For the standard continuation, this sieves all of [b2min, b2].
+    pub fn cost(&self) -> u64 {
+        // the Brent–Suyama tables take e full additions per step, in
+        // place of one differential addition
+        let suyama = self.suyama.map_or(0, |f| {
+            let steps = self.d / 4 + self.giant_steps as u64;
+            (FULL_ADD * f.degree() as u64 - ADD) * steps
+        });
+        suyama
+            + match self.continuation {
+                Continuation::Standard => {
+                    let pairs: u64 = self.pair_windows().map(|w| w.pair_count()).sum();
+                    overhead(self.b2min, self.b2, self.d) + 2 * pairs
+                }
+                Continuation::Fft => fft_overhead(self.b2min, self.b2, self.d),
+            }
+    }
+}
+
+/// The pairs of a window of consecutive giant steps: bit i of giant step
+/// g tells whether the pair (g, baby_steps[i]) covers a prime
+#[derive(Debug)]
+pub struct PairWindow {
+    steps: Range<usize>,
+    /// Words of each giant step's bitmap
+    words: usize,
+    bitmaps: Vec<u64>,
+}
+
+impl PairWindow {
+    /// The giant steps of the window, counted from the first one of the
+    /// plan
+    pub fn steps(&self) -> Range<usize> {
+        self.steps.clone()
+    }
+
+    /// Indices in `baby_steps` of the pairs to multiply at the given
+    /// giant step of the window
+    pub fn pairs(&self, step: usize) -> impl Iterator<Item = usize> + '_ {
+        assert!(
+            self.steps.contains(&step),
+            "giant step {} is not in the window {:?}",
+            step,
+            self.steps
+        );
+        let k = step - self.steps.start;
+        let bitmap = &self.bitmaps[k * self.words..(k + 1) * self.words];
+        bitmap.iter().enumerate().flat_map(|(w, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(64 * w + bit)
+            })
+        })
+    }
+
+    /// Number of pairs in the window
+    pub fn pair_count(&self) -> u64 {
+        self.bitmaps.iter().map(|w| w.count_ones() as u64).sum()
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn euler_phi(mut n: u64) -> u64 {
+    let mut phi = n;
+    let mut p = 2;
+    while p * p <= n {
+        if n.is_multiple_of(p) {
+            phi -= phi / p;
+            while n.is_multiple_of(p) {
+                n /= p;
+            }
+        }
+        p += 1;
+    }
+    if n > 1 {
+        phi -= phi / n;
+    }
+    phi
+}
+
+/// Multiplications for the baby steps (through all odd j < d/2) and the
+/// giant steps, which is all of stage 2 that depends on d
+fn overhead(b2min: u64, b2: u64, d: u64) -> u64 {
+    let giant_steps = b2.saturating_sub(b2min) / d + 1;
+    ADD * (d / 4) + euler_phi(d) / 2 + (ADD + 1) * giant_steps
+}
+
+/// Multiplications for the FFT continuation: the baby and giant steps,
+/// normalizing the giant steps, and for each block of as many giant steps
+/// as there are baby steps, a product tree and a product mod F. The
+/// polynomial products are counted as m log m multiplications of Z/nZ.
+fn fft_overhead(b2min: u64, b2: u64, d: u64) -> u64 {
+    let giant_steps = b2.saturating_sub(b2min) / d + 1;
+    let m = euler_phi(d) / 2;
+    let log = 64 - m.leading_zeros() as u64;
+    let blocks = giant_steps.div_ceil(m);
+    ADD * (d / 4) + (ADD + 4) * giant_steps + (blocks + 1) * m * log * log
+}
+
+/// The d minimizing the given overhead among the multiples of `WHEELS`
+/// with at most max_baby_steps baby steps. d is kept below 2 b2min, so
+/// that every giant step is a nonzero multiple of Q.
+fn choose_d(b2min: u64, b2: u64, max_baby_steps: usize, overhead: fn(u64, u64, u64) -> u64) -> u64 {
+    let max_d = (2 * b2min).max(WHEELS[0]);
+    let mut candidates = vec![];
+    for (k, &wheel) in WHEELS.iter().enumerate() {
+        let mut d = wheel;
+        while d <= max_d
+            && euler_phi(d) / 2 <= max_baby_steps as u64
+            && WHEELS.get(k + 1).is_none_or(|&next| d < next)
+        {
+            candidates.push(d);
+            d += wheel;
+        }
+    }
+    candidates
+        .into_iter()
+        .min_by_key(|&d| overhead(b2min, b2, d))
+        .unwrap_or(WHEELS[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arith::integer::Integer;
+    use crate::arith::integer::Pow;
+
+    #[test]
+    fn stage1_plan_tests() {
+        let plan = Stage1Plan::new(10000);
+        assert_eq!(plan.doublings(), 13);
+        assert_eq!(plan.chains().len(), 1228);
+        assert_eq!(plan.chains()[0].0.k(), 3);
+        assert_eq!(plan.chains()[0].1, 8);
+        // the ladder on the product of the prime powers costs 11
+        // multiplications per bit
+        let mut k = Integer::from(1) << plan.doublings();
+        for (chain, e) in plan.chains() {
+            k *= Integer::from(chain.k()).pow(*e);
+        }
+        assert!(plan.cost() < 11 * k.significant_bits() as u64 * 9 / 10);
+        // built on demand only
+        assert!(plan.scalar.get().is_none());
+        assert_eq!(*plan.scalar(), k);
+        assert_eq!(*Stage1Plan::new(1).scalar(), 1);
+    }
+
+    #[test]
+    fn stage2_plan_tests() {
+        let (b1, b2) = (1000, 3_000_000);
+        let plan = Stage2Plan::new(b1, b2);
+        let d = plan.d();
+        assert_eq!(d, 1890);
+        assert_eq!(plan.baby_steps().len() as u64, euler_phi(d) / 2);
+        assert_eq!(plan.first_giant_step(), 1);
+        // every prime in (b1, b2] is covered by exactly one pair, and
+        // every pair covers a prime
+        let table = PrimeTable::new(0..b2 + d);
+        let in_range = |p: u64| p > b1 && p <= b2 && table.is_prime(p);
+        let mut covered = vec![];
+        let mut pairs = 0;
+        let mut steps = 0;
+        for window in plan.pair_windows() {
+            assert_eq!(window.steps().start, steps);
+            assert!(window.steps().len() <= plan.window_steps());
+            steps = window.steps().end;
+            for step in window.steps() {
+                let g = plan.first_giant_step() + step as u64;
+                for i in window.pairs(step) {
+                    let j = plan.baby_steps()[i];
+                    let primes: Vec<u64> = [g * d - j, g * d + j]
+                        .iter()
+                        .copied()
+                        .filter(|&p| in_range(p))
+                        .collect();
+                    assert!(!primes.is_empty(), "g={} j={}", g, j);
+                    covered.extend(primes);
+                    pairs += 1;
+                }
+            }
+        }
+        assert_eq!(steps, plan.giant_steps());
+        // several windows, each much smaller than the range
+        assert!(plan.window_steps() < plan.giant_steps() / 2);
+        covered.sort_unstable();
+        assert_eq!(covered, table.primes_in(b1 + 1..b2 + 1).collect::<Vec<_>>());
+        // pairing saves about a fifth of the multiplications
+        assert!(pairs < covered.len() * 9 / 10);
+    }
+
+    #[test]
+    fn stage2_plan_memory_bound() {
+        for max_baby_steps in [10, 50, 100, 1000] {
+            let plan = Stage2Plan::with_max_baby_steps(100000, 10_000_000, max_baby_steps);
+            assert!(plan.d().is_multiple_of(6));
+            assert!(plan.baby_steps().len() <= max_baby_steps);
+        }
+        let small = Stage2Plan::with_max_baby_steps(100000, 10_000_000, 10);
+        let large = Stage2Plan::with_max_baby_steps(100000, 10_000_000, 1000);
+        assert!(large.d() > small.d());
+        // nothing to do when b2 <= b1
+        assert_eq!(Stage2Plan::new(1000, 1000).giant_steps(), 0);
+    }
+
+    #[test]
+    fn stage2_fft_plan() {
+        // no sieving, so any b2 goes
+        let (b1, b2) = (1_000_000, 10_000_000_000_000);
+        let plan = Stage2Plan::fft(b1, b2);
+        assert_eq!(plan.continuation(), Continuation::Fft);
+        assert!(plan.baby_steps().len() <= DEFAULT_MAX_BABY_STEPS);
+        let d = plan.d();
+        let first = plan.first_giant_step();
+        let last = first + plan.giant_steps() as u64 - 1;
+        assert!(first * d - d / 2 <= b1 + 1 && first * d + d / 2 > b1 + 1);
+        assert!(last * d - d / 2 <= b2 && last * d + d / 2 > b2);
+        // large products call for large polynomials
+        assert!(plan.d() > Stage2Plan::fft(b1, 100 * b1).d());
+        assert!(plan.cost() < Stage2Plan::fft_with_max_baby_steps(b1, b2, 1000).cost());
+    }
+
+    #[test]
+    fn stage2_range_plan() {
+        // [1001, 3000000] split in two ranges covers the same primes
+        let (b1, mid, b2) = (1000, 2_000_000, 3_000_000);
+        let table = PrimeTable::new(0..b2 + 1);
+        let mut covered: Vec<u64> = vec![];
+        for (lo, hi) in [(b1 + 1, mid), (mid + 1, b2)] {
+            let plan = Stage2Plan::range(Continuation::Standard, lo, hi);
+            assert_eq!((plan.b2min(), plan.b2()), (lo, hi));
+            let d = plan.d();
+            assert!(plan.first_giant_step() * d <= lo + d / 2);
+            for window in plan.pair_windows() {
+                for step in window.steps() {
+                    let g = plan.first_giant_step() + step as u64;
+                    for i in window.pairs(step) {
+                        let j = plan.baby_steps()[i];
+                        covered.extend(
+                            [g * d - j, g * d + j]
+                                .iter()
+                                .filter(|&&p| p >= lo && p <= hi && table.is_prime(p)),
+                        );
+                    }
+                }
+            }
+        }
+        covered.sort_unstable();
+        assert_eq!(covered, table.primes_in(b1 + 1..b2 + 1).collect::<Vec<_>>());
+        assert_eq!(Stage2Plan::range(Continuation::Fft, 10, 9).giant_steps(), 0);
+    }
+
+    #[test]
+    fn suyama_polynomial_tests() {
+        // D_4(x, -1) = x^4 + 4x^2 + 2
+        let dickson = SuyamaPolynomial::Dickson(4);
+        assert_eq!(dickson.eval(3), 81 + 36 + 2);
+        assert_eq!(SuyamaPolynomial::Power(5).eval(3), 243);
+        for f in [dickson, SuyamaPolynomial::Power(6)] {
+            // walking the table gives f(x0), f(x0 + h), ...
+            let (x0, h) = (7, 30);
+            let mut table = f.differences(x0, h);
+            assert_eq!(table.len(), f.degree() as usize + 1);
+            for i in 0..20 {
+                assert_eq!(table[0], f.eval(x0 + i * h));
+                for k in 0..f.degree() as usize {
+                    let next = table[k + 1].clone();
+                    table[k] += next;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Stage 1 residues in GMP-ECM's save-file format, one curve per line:
+//!
+//! `METHOD=ECM; PARAM=0; SIGMA=...; B1=...; N=...; X=0x...; CHECKSUM=...; PROGRAM=...;`
+//!
+//! X is the x-coordinate X/Z mod n of the point after stage 1. Files
+//! written by GMP-ECM carry more fields (Y, X0, WHO, TIME, ...), which
+//! are ignored when reading.
+//!
+//! Stage 1 checkpoints use the same kind of line, with the point (X:Z)
+//! reached and the last prime processed.
+use crate::arith::integer::Integer;
+use crate::parametrization::Parametrization;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Modulus of the checksums, the largest prime below 2^32
+const CHECKSUM_MODULUS: u64 = 4294967291;
+
+/// Stage 1 residue of a curve, as saved to and read from a save file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Residue {
+    pub n: Integer,
+    /// Parametrization of the curve, in GMP-ECM's numbering
+    pub param: u32,
+    pub sigma: Integer,
+    pub b1: u64,
+    /// x-coordinate of the point after stage 1, in [0, n)
+    pub x: Integer,
+    pub program: String,
+}
+
+/// Why a save-file line could not be read
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    MissingField(&'static str),
+    InvalidField(String),
+    /// Another method than ECM, such as P-1 or P+1
+    UnsupportedMethod(String),
+    UnsupportedParam(u32),
+    BadChecksum,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::MissingField(key) => write!(f, "missing field {}", key),
+            ParseError::InvalidField(field) => write!(f, "invalid field {}", field),
+            ParseError::UnsupportedMethod(method) => write!(f, "unsupported method {}", method),
+            ParseError::UnsupportedParam(param) => write!(f, "unsupported param {}", param),
+            ParseError::BadChecksum => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Residue {
+    /// GMP-ECM's checksum
+    /// B1 * (sigma mod M) * (param + 1) * (n mod M) * (X mod M) mod M,
+    /// M = 4294967291
+    pub fn checksum(&self) -> u64 {
+        let m = CHECKSUM_MODULUS;
+        [
+            self.b1 % m,
+            self.sigma.mod_u(m as u32) as u64,
+            (self.param as u64 + 1) % m,
+            self.n.mod_u(m as u32) as u64,
+            self.x.mod_u(m as u32) as u64,
+        ]
+        .iter()
+        .fold(1, |acc, v| acc * v % m)
+    }
+
+    /// Parses a line of a save file. The fields may come in any order,
+    /// the checksum is checked if present.
+    pub fn parse(line: &str) -> Result<Residue, ParseError> {
+        let fields = Fields::parse(line)?;
+        let method = fields.require("METHOD")?;
+        if !method.eq_ignore_ascii_case("ECM") {
+            return Err(ParseError::UnsupportedMethod(method.to_string()));
+        }
+        // GMP-ECM leaves PARAM out for Suyama's parametrization
+        let param = match fields.get("PARAM") {
+            Some(_) => fields.parse_as("PARAM")?,
+            None => 0,
+        };
+        if Parametrization::from_param(param).is_none() {
+            return Err(ParseError::UnsupportedParam(param));
+        }
+        let b1 = fields.require("B1")?;
+        // B1 may be written as a float, e.g. 1e6
+        let b1 = match b1.parse::<u64>() {
+            Ok(b1) => b1,
+            Err(_) => b1
+                .parse::<f64>()
+                .ok()
+                .filter(|b| *b >= 0.0 && b.fract() == 0.0)
+                .ok_or_else(|| invalid("B1", b1))? as u64,
+        };
+        let residue = Residue {
+            n: fields.number("N")?,
+            param,
+            sigma: fields.number("SIGMA")?,
+            b1,
+            x: fields.number("X")?,
+            program: fields.get("PROGRAM").unwrap_or("").to_string(),
+        };
+        if fields.get("CHECKSUM").is_some()
+            && fields.parse_as::<u64>("CHECKSUM")? != residue.checksum()
+        {
+            return Err(ParseError::BadChecksum);
+        }
+        Ok(residue)
+    }
+
+    /// Parses the lines of a save file, skipping blank lines and comments
+    pub fn parse_all(contents: &str) -> Result<Vec<Residue>, ParseError> {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Residue::parse)
+            .collect()
+    }
+}
+
+/// The KEY=value fields of a line, separated by semicolons
+struct Fields<'a>(Vec<(&'a str, &'a str)>);
+
+impl<'a> Fields<'a> {
+    fn parse(line: &'a str) -> Result<Fields<'a>, ParseError> {
+        line.split(';')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .map(|field| match field.split_once('=') {
+                Some((key, value)) => Ok((key.trim(), value.trim())),
+                None => Err(ParseError::InvalidField(field.to_string())),
+            })
+            .collect::<Result<_, _>>()
+            .map(Fields)
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|&(_, v)| v)
+    }
+
+    fn require(&self, key: &'static str) -> Result<&'a str, ParseError> {
+        self.get(key).ok_or(ParseError::MissingField(key))
+    }
+
+    fn parse_as<T: FromStr>(&self, key: &'static str) -> Result<T, ParseError> {
+        let value = self.require(key)?;
+        value.parse().map_err(|_| invalid(key, value))
+    }
+
+    /// A number in decimal, or in hexadecimal after 0x
+    fn number(&self, key: &'static str) -> Result<Integer, ParseError> {
+        let value = self.require(key)?;
+        let parsed = match value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+        {
+            Some(hex) => Integer::from_str_radix(hex, 16),
+            None => Integer::from_str(value),
+        };
+        parsed.map_err(|_| invalid(key, value))
+    }
+}
+
+fn invalid(key: &str, value: &str) -> ParseError {
+    ParseError::InvalidField(format!("{}={}", key, value))
+}
+
+impl fmt::Display for Residue {
+    /// The line of a save file, without the newline
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "METHOD=ECM; PARAM={}; SIGMA={}; B1={}; N={}; X={:#x}; CHECKSUM={}; PROGRAM={};",
+            self.param,
+            self.sigma,
+            self.b1,
+            self.n,
+            self.x,
+            self.checksum(),
+            self.program
+        )
+    }
+}
+
+/// Save file the stage 1 residues are appended to, shared by the threads
+#[derive(Debug)]
+pub struct SaveFile {
+    file: Mutex<File>,
+}
+
+impl SaveFile {
+    /// Opens path for appending, creating it if needed
+    pub fn append_to<P: AsRef<Path>>(path: P) -> io::Result<SaveFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(SaveFile {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Writes the residue as one line
+    pub fn save(&self, residue: &Residue) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", residue)?;
+        file.flush()
+    }
+}
+
+/// State of stage 1 on a curve: the point (X:Z) reached once all the
+/// prime powers up to `prime` are done. `prime` = B1 means stage 1 is
+/// over.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    pub n: Integer,
+    pub param: u32,
+    pub sigma: Integer,
+    pub b1: u64,
+    pub prime: u64,
+    pub x: Integer,
+    pub z: Integer,
+}
+
+impl Checkpoint {
+    pub fn parse(line: &str) -> Result<Checkpoint, ParseError> {
+        let fields = Fields::parse(line)?;
+        Ok(Checkpoint {
+            n: fields.number("N")?,
+            param: fields.parse_as("PARAM")?,
+            sigma: fields.number("SIGMA")?,
+            b1: fields.parse_as("B1")?,
+            prime: fields.parse_as("PRIME")?,
+            x: fields.number("X")?,
+            z: fields.number("Z")?,
+        })
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "N={}; PARAM={}; SIGMA={}; B1={}; PRIME={}; X={:#x}; Z={:#x};",
+            self.n, self.param, self.sigma, self.b1, self.prime, self.x, self.z
+        )
+    }
+}
+
+/// Where and how often stage 1 checkpoints: thread i keeps its
+/// checkpoint in `<path>.<i>`, rewritten at most every `interval`.
+#[derive(Clone, Debug)]
+pub struct Checkpoints {
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl Checkpoints {
+    pub fn new<P: AsRef<Path>>(path: P, interval: Duration) -> Checkpoints {
+        Checkpoints {
+            path: path.as_ref().to_path_buf(),
+            interval,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The checkpoint file of thread thread_no
+    pub fn path(&self, thread_no: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", thread_no));
+        PathBuf::from(name)
+    }
+
+    /// The checkpoint of thread thread_no, if there is one for n and b1
+    pub fn load(&self, thread_no: usize, n: &Integer, b1: u64) -> Option<Checkpoint> {
+        let contents = std::fs::read_to_string(self.path(thread_no)).ok()?;
+        Checkpoint::parse(contents.trim())
+            .ok()
+            .filter(|c| c.n == *n && c.b1 == b1)
+    }
+
+    /// Writes the checkpoint to a temporary file first, renamed over the
+    /// previous checkpoint once complete, so that a crash leaves either
+    /// checkpoint intact
+    pub fn store(&self, thread_no: usize, checkpoint: &Checkpoint) -> io::Result<()> {
+        let path = self.path(thread_no);
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        writeln!(file, "{}", checkpoint)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &path)
+    }
+
+    /// Removes the checkpoint of thread thread_no, once its curve is done
+    pub fn clear(&self, thread_no: usize) -> io::Result<()> {
+        match std::fs::remove_file(self.path(thread_no)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Name written to the PROGRAM field
+pub fn program_name() -> String {
+    format!("ecm-rs {}", env!("CARGO_PKG_VERSION"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_line_tests() {
+        let residue = Residue {
+            n: Integer::from(1000003u64 * 999983),
+            param: 0,
+            sigma: Integer::from(12345),
+            b1: 1000000,
+            x: Integer::from(0xdeadbeefu64),
+            program: program_name(),
+        };
+        let line = residue.to_string();
+        assert!(line.starts_with("METHOD=ECM; PARAM=0; SIGMA=12345; B1=1000000; "));
+        assert!(line.contains("X=0xdeadbeef;"));
+        assert_eq!(Residue::parse(&line), Ok(residue.clone()));
+
+        // a line laid out as GMP-ECM writes them, fields in another order
+        // and extra ones included. It is not the output of a GMP-ECM run:
+        // its checksum is worked out by hand from GMP-ECM's formula,
+        // 10^6 * 12345 * (0 + 1) * (N mod M) * (0xdeadbeef mod M) mod M
+        assert_eq!(residue.checksum(), 3702054982);
+        let gmp_ecm = format!(
+            "METHOD=ECM; SIGMA=12345; B1=1e6; N={}; X=0xDEADBEEF; CHECKSUM=3702054982; PROGRAM=GMP-ECM 7.0.4; Y=0x0; X0=0x0; Y0=0x0; WHO=me@host; TIME=Mon Jan  1 00:00:00 2024;",
+            residue.n
+        );
+        let parsed = Residue::parse(&gmp_ecm).unwrap();
+        assert_eq!((parsed.x, parsed.b1), (residue.x.clone(), residue.b1));
+        assert_eq!(
+            Residue::parse(&line.replace("SIGMA=12345", "SIGMA=12346")),
+            Err(ParseError::BadChecksum)
+        );
+        assert_eq!(
+            Residue::parse("METHOD=P-1; B1=100; N=15; X=0x2;"),
+            Err(ParseError::UnsupportedMethod("P-1".to_string()))
+        );
+        assert_eq!(
+            Residue::parse("METHOD=ECM; SIGMA=7; N=15; X=0x2;"),
+            Err(ParseError::MissingField("B1"))
+        );
+        // params 1 to 3 are read, the checksum covering the param
+        let batch = Residue {
+            param: 3,
+            ..residue.clone()
+        };
+        assert_eq!(Residue::parse(&batch.to_string()), Ok(batch.clone()));
+        assert_eq!(
+            Residue::parse(&batch.to_string().replace("PARAM=3", "PARAM=1")),
+            Err(ParseError::BadChecksum)
+        );
+        assert_eq!(
+            Residue::parse(&line.replace("PARAM=0", "PARAM=4")),
+            Err(ParseError::UnsupportedParam(4))
+        );
+        let file = format!("# comment\n\n{}\n{}\n", line, line);
+        assert_eq!(Residue::parse_all(&file).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn checkpoint_tests() {
+        let checkpoint = Checkpoint {
+            n: Integer::from(1000003u64 * 999983),
+            param: 2,
+            sigma: Integer::from(12345),
+            b1: 1000000,
+            prime: 7919,
+            x: Integer::from(0xabcdefu64),
+            z: Integer::from(0x123456u64),
+        };
+        assert_eq!(
+            Checkpoint::parse(&checkpoint.to_string()),
+            Ok(checkpoint.clone())
+        );
+
+        let base = std::env::temp_dir().join(format!("ecm-checkpoint-{}", std::process::id()));
+        let checkpoints = Checkpoints::new(&base, Duration::from_secs(60));
+        checkpoints.store(3, &checkpoint).unwrap();
+        assert!(checkpoints.path(3).to_str().unwrap().ends_with(".3"));
+        assert_eq!(
+            checkpoints.load(3, &checkpoint.n, checkpoint.b1),
+            Some(checkpoint.clone())
+        );
+        // another number, another bound or another thread
+        assert_eq!(checkpoints.load(3, &Integer::from(15), checkpoint.b1), None);
+        assert_eq!(checkpoints.load(3, &checkpoint.n, 1000), None);
+        assert_eq!(checkpoints.load(2, &checkpoint.n, checkpoint.b1), None);
+        checkpoints.clear(3).unwrap();
+        assert_eq!(checkpoints.load(3, &checkpoint.n, checkpoint.b1), None);
+        checkpoints.clear(3).unwrap();
+    }
+}